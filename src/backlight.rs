@@ -0,0 +1,417 @@
+//! Backlight control with gamma corrected brightness, fades and an inactivity timeout.
+//!
+//! The backlight is driven by any embedded-hal PWM (`SetDutyCycle`), on the ESP32S3 this is the `LedcDriver`.
+//! After `dim_timeout` without a touch the backlight fades down to `dim_brightness` and after `off_timeout` it
+//! fades off. A touch wakes the backlight, the touch that wakes it is swallowed so it does not also press a
+//! button the user could not see.
+//!
+//! The time is passed in by the caller (the time since any fixed point in time) so the dimming curve and the
+//! timeouts do not depend on any hardware.
+use core::time::Duration;
+
+use embedded_hal::pwm::SetDutyCycle;
+
+/// Converts a perceived brightness (0 - 100%) into a PWM duty cycle.
+///
+/// The eye's response to light is roughly a power curve so the duty cycle is `(percent / 100) ^ gamma`, a
+/// brightness above 0% always gives a duty cycle of at least 1 so the backlight does not turn off.
+pub fn gamma_duty(percent: f32, gamma: f32, max_duty: u16) -> u16 {
+    let level = (percent / 100.0).clamp(0.0, 1.0);
+    if level == 0.0 {
+        return 0;
+    }
+
+    let duty = (level.powf(gamma) * max_duty as f32).round() as u16;
+    duty.max(1)
+}
+
+/// The state of the backlight
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BacklightState {
+    On,
+    Dimmed,
+    Off,
+}
+
+/// Brightness levels, fade durations and timeouts of the backlight
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BacklightConfig {
+    /// Brightness (0 - 100%) when the backlight is dimmed
+    pub dim_brightness: u8,
+    /// Time without a touch before the backlight is dimmed, None to never dim
+    pub dim_timeout: Option<Duration>,
+    /// Time without a touch before the backlight is turned off, None to never turn off
+    pub off_timeout: Option<Duration>,
+    /// Time taken to dim or turn off the backlight
+    pub fade_duration: Duration,
+    /// Time taken to wake the backlight
+    pub wake_duration: Duration,
+    pub gamma: f32,
+    /// Ignore the touch that wakes the backlight
+    pub swallow_wake_touch: bool,
+}
+
+impl BacklightConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn dim_brightness(mut self, percent: u8) -> Self {
+        self.dim_brightness = percent.min(100);
+        self
+    }
+
+    #[must_use]
+    pub fn dim_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.dim_timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    pub fn off_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.off_timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    pub fn fade_duration(mut self, duration: Duration) -> Self {
+        self.fade_duration = duration;
+        self
+    }
+
+    #[must_use]
+    pub fn wake_duration(mut self, duration: Duration) -> Self {
+        self.wake_duration = duration;
+        self
+    }
+
+    #[must_use]
+    pub fn gamma(mut self, gamma: f32) -> Self {
+        self.gamma = gamma;
+        self
+    }
+
+    #[must_use]
+    pub fn swallow_wake_touch(mut self, enable: bool) -> Self {
+        self.swallow_wake_touch = enable;
+        self
+    }
+}
+
+impl Default for BacklightConfig {
+    fn default() -> Self {
+        Self {
+            dim_brightness: 20,
+            dim_timeout: Some(Duration::from_secs(30)),
+            off_timeout: Some(Duration::from_secs(60)),
+            fade_duration: Duration::from_millis(500),
+            wake_duration: Duration::from_millis(150),
+            gamma: 2.2,
+            swallow_wake_touch: true,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Fade {
+    from: f32,
+    to: f32,
+    start: Duration,
+    duration: Duration,
+}
+
+impl Fade {
+    // The brightness at `now`, linear in perceived brightness
+    fn level_at(&self, now: Duration) -> f32 {
+        let elapsed = now.saturating_sub(self.start);
+        if elapsed >= self.duration {
+            return self.to;
+        }
+
+        let t = elapsed.as_secs_f32() / self.duration.as_secs_f32();
+        self.from + (self.to - self.from) * t
+    }
+}
+
+/// Controls the backlight brightness
+pub struct Backlight<P: SetDutyCycle> {
+    pwm: P,
+    config: BacklightConfig,
+    brightness: u8,
+    level: f32,
+    duty: Option<u16>,
+    fade: Option<Fade>,
+    state: BacklightState,
+    last_activity: Duration,
+    swallowing: bool,
+}
+
+impl<P: SetDutyCycle> Backlight<P> {
+    /// Turns the backlight on at full brightness, `now` starts the inactivity timeout
+    pub fn new(pwm: P, config: BacklightConfig, now: Duration) -> Result<Self, P::Error> {
+        let mut backlight = Self {
+            pwm,
+            config,
+            brightness: 100,
+            level: 100.0,
+            duty: None,
+            fade: None,
+            state: BacklightState::On,
+            last_activity: now,
+            swallowing: false,
+        };
+        backlight.write_level()?;
+
+        Ok(backlight)
+    }
+
+    pub fn config(&self) -> &BacklightConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: BacklightConfig) {
+        self.config = config;
+    }
+
+    /// The brightness (0 - 100%) when the backlight is on
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// The brightness (0 - 100%) currently output, this changes during a fade
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    pub fn state(&self) -> BacklightState {
+        self.state
+    }
+
+    /// Sets the brightness (0 - 100%) used when the backlight is on, the backlight fades to the new brightness
+    /// over `duration` if it is on.
+    pub fn set_brightness(&mut self, percent: u8, duration: Duration, now: Duration) {
+        self.brightness = percent.min(100);
+        if self.state == BacklightState::On {
+            self.fade_to(self.brightness as f32, duration, now);
+        }
+    }
+
+    /// Records a touch. Returns false if the touch must be ignored because it is waking the backlight, the touch
+    /// is ignored until it is released.
+    pub fn touch(&mut self, pressed: bool, now: Duration) -> bool {
+        if pressed {
+            self.last_activity = now;
+        }
+
+        if self.swallowing {
+            self.swallowing = pressed;
+            return false;
+        }
+
+        if pressed && self.state != BacklightState::On {
+            self.wake(now);
+            if self.config.swallow_wake_touch {
+                self.swallowing = true;
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Turns the backlight back on and restarts the inactivity timeout
+    pub fn wake(&mut self, now: Duration) {
+        self.last_activity = now;
+        if self.state != BacklightState::On {
+            self.state = BacklightState::On;
+            self.fade_to(self.brightness as f32, self.config.wake_duration, now);
+        }
+    }
+
+    /// Advances the fade and the inactivity timeout, call this regularly (eg from the Lvgl loop)
+    pub fn update(&mut self, now: Duration) -> Result<(), P::Error> {
+        let idle = now.saturating_sub(self.last_activity);
+        let expired = |timeout: Option<Duration>| timeout.is_some_and(|timeout| idle >= timeout);
+
+        let state = if expired(self.config.off_timeout) {
+            BacklightState::Off
+        } else if expired(self.config.dim_timeout) {
+            BacklightState::Dimmed
+        } else {
+            self.state
+        };
+
+        if state != self.state {
+            self.state = state;
+            let target = match state {
+                BacklightState::On => self.brightness,
+                BacklightState::Dimmed => self.config.dim_brightness.min(self.brightness),
+                BacklightState::Off => 0,
+            };
+            self.fade_to(target as f32, self.config.fade_duration, now);
+        }
+
+        if let Some(fade) = self.fade {
+            self.level = fade.level_at(now);
+            if now.saturating_sub(fade.start) >= fade.duration {
+                self.fade = None;
+            }
+        }
+
+        self.write_level()
+    }
+
+    pub fn release(self) -> P {
+        self.pwm
+    }
+
+    // Start a fade from the current level
+    fn fade_to(&mut self, level: f32, duration: Duration, now: Duration) {
+        self.fade = Some(Fade {
+            from: self.level,
+            to: level,
+            start: now,
+            duration,
+        });
+    }
+
+    // Only write the duty cycle when it changes
+    fn write_level(&mut self) -> Result<(), P::Error> {
+        let duty = gamma_duty(self.level, self.config.gamma, self.pwm.max_duty_cycle());
+        if self.duty != Some(duty) {
+            self.pwm.set_duty_cycle(duty)?;
+            self.duty = Some(duty);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use core::convert::Infallible;
+    use std::rc::Rc;
+
+    use embedded_hal::pwm::ErrorType;
+
+    const MAX_DUTY: u16 = 1000;
+
+    // A PWM that shares the last duty cycle written with the test
+    struct MockPwm(Rc<Cell<u16>>);
+
+    impl ErrorType for MockPwm {
+        type Error = Infallible;
+    }
+
+    impl SetDutyCycle for MockPwm {
+        fn max_duty_cycle(&self) -> u16 {
+            MAX_DUTY
+        }
+
+        fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+            self.0.set(duty);
+            Ok(())
+        }
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn backlight(config: BacklightConfig) -> (Backlight<MockPwm>, Rc<Cell<u16>>) {
+        let duty = Rc::new(Cell::new(0));
+        let backlight = Backlight::new(MockPwm(duty.clone()), config, ms(0)).unwrap();
+        (backlight, duty)
+    }
+
+    #[test]
+    fn gamma_endpoints() {
+        assert_eq!(gamma_duty(0.0, 2.2, MAX_DUTY), 0);
+        assert_eq!(gamma_duty(100.0, 2.2, MAX_DUTY), MAX_DUTY);
+        assert_eq!(gamma_duty(-5.0, 2.2, MAX_DUTY), 0);
+        assert_eq!(gamma_duty(150.0, 2.2, MAX_DUTY), MAX_DUTY);
+
+        // 50% perceived brightness is about 22% duty cycle and a dim backlight never turns off
+        assert_eq!(gamma_duty(50.0, 2.2, MAX_DUTY), 218);
+        assert_eq!(gamma_duty(1.0, 2.2, MAX_DUTY), 1);
+        assert_eq!(gamma_duty(50.0, 1.0, MAX_DUTY), 500);
+    }
+
+    #[test]
+    fn fade_interpolation() {
+        let (mut backlight, duty) = backlight(BacklightConfig::new());
+        assert_eq!(duty.get(), MAX_DUTY);
+
+        // The fade is linear in perceived brightness
+        backlight.set_brightness(0, ms(1000), ms(0));
+        backlight.update(ms(250)).unwrap();
+        assert!((backlight.level() - 75.0).abs() < 0.01);
+        assert_eq!(duty.get(), gamma_duty(75.0, 2.2, MAX_DUTY));
+        backlight.update(ms(500)).unwrap();
+        assert!((backlight.level() - 50.0).abs() < 0.01);
+        backlight.update(ms(2000)).unwrap();
+        assert_eq!(backlight.level(), 0.0);
+        assert_eq!(duty.get(), 0);
+    }
+
+    #[test]
+    fn dims_then_turns_off() {
+        let (mut backlight, duty) = backlight(BacklightConfig::new());
+
+        backlight.update(ms(29_999)).unwrap();
+        assert_eq!(backlight.state(), BacklightState::On);
+
+        backlight.update(ms(30_000)).unwrap();
+        assert_eq!(backlight.state(), BacklightState::Dimmed);
+        backlight.update(ms(30_250)).unwrap();
+        assert!((backlight.level() - 60.0).abs() < 0.01);
+        backlight.update(ms(31_000)).unwrap();
+        assert_eq!(duty.get(), gamma_duty(20.0, 2.2, MAX_DUTY));
+
+        backlight.update(ms(60_000)).unwrap();
+        assert_eq!(backlight.state(), BacklightState::Off);
+        backlight.update(ms(60_500)).unwrap();
+        assert_eq!(duty.get(), 0);
+    }
+
+    #[test]
+    fn touch_restarts_the_timeout() {
+        let (mut backlight, _) = backlight(BacklightConfig::new());
+
+        assert!(backlight.touch(true, ms(20_000)));
+        backlight.update(ms(45_000)).unwrap();
+        assert_eq!(backlight.state(), BacklightState::On);
+        backlight.update(ms(50_000)).unwrap();
+        assert_eq!(backlight.state(), BacklightState::Dimmed);
+    }
+
+    #[test]
+    fn wake_touch_is_swallowed() {
+        let (mut backlight, duty) = backlight(BacklightConfig::new());
+        backlight.update(ms(60_000)).unwrap();
+        backlight.update(ms(61_000)).unwrap();
+        assert_eq!(backlight.state(), BacklightState::Off);
+
+        // The waking touch is ignored until it is released
+        assert!(!backlight.touch(true, ms(62_000)));
+        assert_eq!(backlight.state(), BacklightState::On);
+        assert!(!backlight.touch(true, ms(62_030)));
+        assert!(!backlight.touch(false, ms(62_060)));
+        assert!(backlight.touch(true, ms(62_090)));
+
+        backlight.update(ms(62_200)).unwrap();
+        assert_eq!(duty.get(), MAX_DUTY);
+    }
+
+    #[test]
+    fn wake_touch_passed_on() {
+        let (mut backlight, _) = backlight(BacklightConfig::new().swallow_wake_touch(false));
+        backlight.update(ms(60_000)).unwrap();
+
+        assert!(backlight.touch(true, ms(61_000)));
+        assert_eq!(backlight.state(), BacklightState::On);
+    }
+}
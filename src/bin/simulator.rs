@@ -0,0 +1,39 @@
+//! Desktop simulator for the clickme UI.
+//!
+//! Runs the same UI as the ESP32S3 headless on a PC. Lvgl renders into an in-memory 800x480 framebuffer and the
+//! touch input is replayed from a touch recording (see touch_record.rs). When the recording has been replayed the
+//! framebuffer is written out as a PPM image.
+//!
+//! cargo run --bin simulator --features simulator --target x86_64-unknown-linux-gnu -- [recording.csv] [output.ppm]
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::time::Duration;
+
+use rust_esp32s3_lvgl_clickme::simulator::Simulator;
+use rust_esp32s3_lvgl_clickme::touch_record::TouchReplay;
+use rust_esp32s3_lvgl_clickme::ui::create_ui;
+
+// Time to keep running after the last touch event so animations can finish
+const SETTLE_TIME: Duration = Duration::from_millis(1000);
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let mut replay = match args.get(1) {
+        Some(path) => TouchReplay::from_reader(BufReader::new(File::open(path)?))?,
+        None => TouchReplay::new(Vec::new()),
+    };
+    let output = args.get(2).map(String::as_str).unwrap_or("simulator.ppm");
+
+    let mut simulator = Simulator::new();
+    let _ui = create_ui(simulator.display());
+
+    simulator.replay(&mut replay);
+    simulator.run_for(SETTLE_TIME);
+
+    simulator
+        .framebuffer()
+        .write_ppm(BufWriter::new(File::create(output)?))?;
+    println!("Wrote {}", output);
+
+    Ok(())
+}
@@ -0,0 +1,346 @@
+//! Board profiles for ESP32S3 development boards with an RGB LCD panel.
+//!
+//! A `BoardProfile` bundles everything that changes from one board to another: the panel pins and timings,
+//! the touchscreen controller bus, pins and address and the backlight pin. The board is selected at build time
+//! with one of the `board-*` cargo features (the Esp32S-8048S070C when no feature is enabled) or at runtime with
+//! `Board::from_name`.
+use core::fmt;
+
+use crate::gt911::{ALTERNATE_GT911_ADDRESS, DEFAULT_GT911_ADDRESS};
+use crate::panel_config::{PanelConfig, PanelFlagsConfig, TimingFlagsConfig, TimingsConfig};
+
+#[cfg(any(
+    all(
+        feature = "board-8048s070",
+        any(
+            feature = "board-8048s050",
+            feature = "board-4827s043",
+            feature = "board-s3-lcd-ev"
+        )
+    ),
+    all(
+        feature = "board-8048s050",
+        any(feature = "board-4827s043", feature = "board-s3-lcd-ev")
+    ),
+    all(feature = "board-4827s043", feature = "board-s3-lcd-ev")
+))]
+compile_error!("Only one of the board-* features can be enabled");
+
+/// The supported boards
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Board {
+    /// Aliexpress Esp32S-8048S070C, 7 inch 800x480 with GT911 touch
+    Esp8048S070,
+    /// Aliexpress Esp32S-8048S050C, 5 inch 800x480 with GT911 touch
+    Esp8048S050,
+    /// Aliexpress Esp32S-4827S043C, 4.3 inch 480x272 with GT911 touch
+    Esp4827S043,
+    /// Espressif ESP32-S3-LCD-EV-Board with the 4.3 inch 800x480 LCD subboard (GT1151 touch)
+    S3LcdEvBoard,
+}
+
+impl Board {
+    pub const ALL: [Board; 4] = [
+        Board::Esp8048S070,
+        Board::Esp8048S050,
+        Board::Esp4827S043,
+        Board::S3LcdEvBoard,
+    ];
+
+    /// The board selected by the cargo features, enabling more than one board feature is a compile error.
+    pub const fn selected() -> Self {
+        if cfg!(feature = "board-8048s070") {
+            Board::Esp8048S070
+        } else if cfg!(feature = "board-8048s050") {
+            Board::Esp8048S050
+        } else if cfg!(feature = "board-4827s043") {
+            Board::Esp4827S043
+        } else if cfg!(feature = "board-s3-lcd-ev") {
+            Board::S3LcdEvBoard
+        } else {
+            Board::Esp8048S070
+        }
+    }
+
+    /// The board name, the same name as the cargo feature without the `board-` prefix
+    pub fn name(&self) -> &'static str {
+        match self {
+            Board::Esp8048S070 => "8048s070",
+            Board::Esp8048S050 => "8048s050",
+            Board::Esp4827S043 => "4827s043",
+            Board::S3LcdEvBoard => "s3-lcd-ev",
+        }
+    }
+
+    /// Finds a board by its name (case insensitive)
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|board| board.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    pub fn profile(&self) -> BoardProfile {
+        match self {
+            Board::Esp8048S070 => BoardProfile::esp8048s070(),
+            Board::Esp8048S050 => BoardProfile::esp8048s050(),
+            Board::Esp4827S043 => BoardProfile::esp4827s043(),
+            Board::S3LcdEvBoard => BoardProfile::s3_lcd_ev_board(),
+        }
+    }
+}
+
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The touchscreen controller on the board
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TouchController {
+    Gt911,
+    /// The GT1151 uses the same touch point registers as the GT911 so it is read with the GT911 driver, its
+    /// configuration registers are different so `GT911::read_config` and `GT911::write_config` can not be used.
+    Gt1151,
+}
+
+/// The touchscreen controller, I2C bus and pins
+///
+/// Set a GPIO to -1 when the pin is not connected to the ESP32S3.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TouchConfig {
+    pub controller: TouchController,
+    pub sda_gpio_num: i32,
+    pub scl_gpio_num: i32,
+    pub rst_gpio_num: i32,
+    pub int_gpio_num: i32,
+    pub i2c_address: u8,
+    pub i2c_frequency_hz: u32,
+}
+
+impl TouchConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn controller(mut self, controller: TouchController) -> Self {
+        self.controller = controller;
+        self
+    }
+
+    #[must_use]
+    pub fn sda_gpio_num(mut self, sda_gpio_num: i32) -> Self {
+        self.sda_gpio_num = sda_gpio_num;
+        self
+    }
+
+    #[must_use]
+    pub fn scl_gpio_num(mut self, scl_gpio_num: i32) -> Self {
+        self.scl_gpio_num = scl_gpio_num;
+        self
+    }
+
+    #[must_use]
+    pub fn rst_gpio_num(mut self, rst_gpio_num: i32) -> Self {
+        self.rst_gpio_num = rst_gpio_num;
+        self
+    }
+
+    #[must_use]
+    pub fn int_gpio_num(mut self, int_gpio_num: i32) -> Self {
+        self.int_gpio_num = int_gpio_num;
+        self
+    }
+
+    #[must_use]
+    pub fn i2c_address(mut self, i2c_address: u8) -> Self {
+        self.i2c_address = i2c_address;
+        self
+    }
+
+    #[must_use]
+    pub fn i2c_frequency_hz(mut self, hz: u32) -> Self {
+        self.i2c_frequency_hz = hz;
+        self
+    }
+}
+
+impl Default for TouchConfig {
+    fn default() -> Self {
+        Self {
+            controller: TouchController::Gt911,
+            sda_gpio_num: 19,
+            scl_gpio_num: 20,
+            rst_gpio_num: 38,
+            int_gpio_num: -1,
+            i2c_address: DEFAULT_GT911_ADDRESS,
+            i2c_frequency_hz: 100_000,
+        }
+    }
+}
+
+/// Everything needed to drive the panel, touchscreen and backlight of a board
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BoardProfile {
+    pub board: Board,
+    pub panel: PanelConfig,
+    pub panel_flags: PanelFlagsConfig,
+    pub timings: TimingsConfig,
+    pub timing_flags: TimingFlagsConfig,
+    pub touch: TouchConfig,
+    /// -1 when the backlight is always on
+    pub backlight_gpio_num: i32,
+}
+
+impl BoardProfile {
+    /// The profile of the board selected by the cargo features
+    pub fn selected() -> Self {
+        Board::selected().profile()
+    }
+
+    /// The same board using bounce buffers, see `PanelConfig::bounce_buffer`
+    #[must_use]
+    pub fn with_bounce_buffer(mut self) -> Self {
+        self.panel = self.panel.bounce_buffer(&self.timings);
+        self
+    }
+
+    /// The panel width and height in pixels
+    pub fn resolution(&self) -> (u32, u32) {
+        (self.timings.horz_res, self.timings.vert_res)
+    }
+
+    /// Aliexpress Esp32S-8048S070C, these are the default configs
+    pub fn esp8048s070() -> Self {
+        Self {
+            board: Board::Esp8048S070,
+            panel: PanelConfig::new(),
+            panel_flags: PanelFlagsConfig::new(),
+            timings: TimingsConfig::new(),
+            timing_flags: TimingFlagsConfig::new(),
+            touch: TouchConfig::new(),
+            backlight_gpio_num: 2,
+        }
+    }
+
+    /// Aliexpress Esp32S-8048S050C
+    pub fn esp8048s050() -> Self {
+        Self {
+            board: Board::Esp8048S050,
+            panel: PanelConfig::new()
+                .hsync_gpio_num(39)
+                .vsync_gpio_num(41)
+                .de_gpio_num(40)
+                .pclk_gpio_num(42)
+                .data_gpio_nums([8, 3, 46, 9, 1, 5, 6, 7, 15, 16, 4, 45, 48, 47, 21, 14]),
+            panel_flags: PanelFlagsConfig::new(),
+            timings: TimingsConfig::new()
+                .hsync_pulse_width(4)
+                .hsync_back_porch(8)
+                .hsync_front_porch(8)
+                .vsync_pulse_width(4)
+                .vsync_back_porch(8)
+                .vsync_front_porch(8),
+            timing_flags: TimingFlagsConfig::new(),
+            touch: TouchConfig::new(),
+            backlight_gpio_num: 2,
+        }
+    }
+
+    /// Aliexpress Esp32S-4827S043C
+    pub fn esp4827s043() -> Self {
+        Self {
+            board: Board::Esp4827S043,
+            panel: PanelConfig::new()
+                .hsync_gpio_num(39)
+                .vsync_gpio_num(41)
+                .de_gpio_num(40)
+                .pclk_gpio_num(42)
+                .data_gpio_nums([8, 3, 46, 9, 1, 5, 6, 7, 15, 16, 4, 45, 48, 47, 21, 14]),
+            panel_flags: PanelFlagsConfig::new(),
+            timings: TimingsConfig::new()
+                .pclk_hz(9 * 1000 * 1000)
+                .horz_res(480)
+                .vert_res(272)
+                .hsync_pulse_width(4)
+                .hsync_back_porch(43)
+                .hsync_front_porch(8)
+                .vsync_pulse_width(4)
+                .vsync_back_porch(12)
+                .vsync_front_porch(8),
+            timing_flags: TimingFlagsConfig::new(),
+            touch: TouchConfig::new(),
+            backlight_gpio_num: 2,
+        }
+    }
+
+    /// Espressif ESP32-S3-LCD-EV-Board with the 800x480 LCD subboard
+    ///
+    /// The touchscreen reset and the backlight are on the IO expander so they are not controlled from here.
+    pub fn s3_lcd_ev_board() -> Self {
+        Self {
+            board: Board::S3LcdEvBoard,
+            panel: PanelConfig::new()
+                .hsync_gpio_num(46)
+                .vsync_gpio_num(3)
+                .de_gpio_num(17)
+                .pclk_gpio_num(9)
+                .data_gpio_nums([10, 11, 12, 13, 14, 21, 8, 18, 45, 38, 39, 40, 41, 42, 2, 1]),
+            panel_flags: PanelFlagsConfig::new(),
+            timings: TimingsConfig::new()
+                .hsync_pulse_width(4)
+                .hsync_back_porch(8)
+                .hsync_front_porch(8)
+                .vsync_pulse_width(4)
+                .vsync_back_porch(16)
+                .vsync_front_porch(16),
+            timing_flags: TimingFlagsConfig::new().pclk_active_neg(false),
+            touch: TouchConfig::new()
+                .controller(TouchController::Gt1151)
+                .sda_gpio_num(47)
+                .scl_gpio_num(48)
+                .rst_gpio_num(-1)
+                .i2c_address(ALTERNATE_GT911_ADDRESS)
+                .i2c_frequency_hz(400_000),
+            backlight_gpio_num: -1,
+        }
+    }
+}
+
+impl Default for BoardProfile {
+    fn default() -> Self {
+        Self::selected()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_are_valid() {
+        for board in Board::ALL {
+            let profile = board.profile();
+            assert_eq!(profile.board, board);
+            profile
+                .panel
+                .validate(&profile.panel_flags, &profile.timings)
+                .unwrap_or_else(|e| panic!("{}: {}", board, e));
+            profile
+                .with_bounce_buffer()
+                .panel
+                .validate(&profile.panel_flags, &profile.timings)
+                .unwrap_or_else(|e| panic!("{} with bounce buffer: {}", board, e));
+        }
+    }
+
+    #[test]
+    fn names() {
+        for board in Board::ALL {
+            assert_eq!(Board::from_name(board.name()), Some(board));
+        }
+        assert_eq!(Board::from_name(" S3-LCD-EV "), Some(Board::S3LcdEvBoard));
+        assert_eq!(Board::from_name("8048s071"), None);
+    }
+}
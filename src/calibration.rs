@@ -0,0 +1,444 @@
+//! Touch calibration for the GT911 touchscreen.
+//!
+//! The raw GT911 coordinates can be off by several pixels near the edges of the panel. Calibration shows a
+//! sequence of 3 or 5 targets on the screen, records where the GT911 reports each touch and computes an affine
+//! transform (least squares fit) that maps the reported touch points onto the screen.
+//!
+//! The transform is applied by the GT911 driver (see `GT911::set_calibration`) and is saved with the device
+//! settings (see `settings.rs`) so calibration only needs to be done once.
+use core::fmt;
+use core::time::Duration;
+use std::time::Instant;
+
+use cstr_core::CString;
+use log::*;
+
+use lvgl::style::Style;
+use lvgl::widgets::{Btn, Label};
+use lvgl::{Align, Color, Display, Part, Widget};
+
+use crate::gt911::{TouchPoint, TouchState};
+
+/// Size in bytes of a serialized `AffineTransform`
+pub const AFFINE_TRANSFORM_LEN: usize = 24;
+
+/// Errors returned when computing a calibration
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CalibrationError {
+    /// At least 3 samples are required to compute an affine transform
+    NotEnoughSamples(usize),
+    /// The samples are on a line (or the same point) so no transform can be computed
+    Degenerate,
+    /// A target was not touched in time, eg the touch panel is not connected
+    Timeout,
+}
+
+impl fmt::Display for CalibrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalibrationError::NotEnoughSamples(count) => {
+                write!(f, "calibration needs at least 3 samples, got {}", count)
+            }
+            CalibrationError::Degenerate => write!(f, "calibration samples are collinear"),
+            CalibrationError::Timeout => write!(f, "calibration target was not touched in time"),
+        }
+    }
+}
+
+impl std::error::Error for CalibrationError {}
+
+/// Affine transform mapping a touch point onto the screen
+///
+/// - x' = a * x + b * y + c
+/// - y' = d * x + e * y + f
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AffineTransform {
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+    pub d: f32,
+    pub e: f32,
+    pub f: f32,
+}
+
+impl Default for AffineTransform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl AffineTransform {
+    pub fn identity() -> Self {
+        Self {
+            a: 1.0,
+            b: 0.0,
+            c: 0.0,
+            d: 0.0,
+            e: 1.0,
+            f: 0.0,
+        }
+    }
+
+    /// Computes the transform that best maps the `touched` points onto the `targets` (least squares fit).
+    ///
+    /// With exactly 3 samples the transform maps the points exactly, with more samples the error is spread
+    /// over all the samples.
+    pub fn from_samples(
+        touched: &[(f32, f32)],
+        targets: &[(f32, f32)],
+    ) -> Result<Self, CalibrationError> {
+        let count = touched.len().min(targets.len());
+        if count < 3 {
+            return Err(CalibrationError::NotEnoughSamples(count));
+        }
+
+        // Normal equations - the matrix is the same for the x and y rows of the transform
+        let mut m = [[0f64; 3]; 3];
+        let mut rhs_x = [0f64; 3];
+        let mut rhs_y = [0f64; 3];
+
+        for (&(tx, ty), &(sx, sy)) in touched.iter().zip(targets.iter()) {
+            let row = [tx as f64, ty as f64, 1.0];
+            for i in 0..3 {
+                for j in 0..3 {
+                    m[i][j] += row[i] * row[j];
+                }
+                rhs_x[i] += row[i] * sx as f64;
+                rhs_y[i] += row[i] * sy as f64;
+            }
+        }
+
+        let [a, b, c] = solve3(&m, &rhs_x).ok_or(CalibrationError::Degenerate)?;
+        let [d, e, f] = solve3(&m, &rhs_y).ok_or(CalibrationError::Degenerate)?;
+
+        Ok(Self {
+            a: a as f32,
+            b: b as f32,
+            c: c as f32,
+            d: d as f32,
+            e: e as f32,
+            f: f as f32,
+        })
+    }
+
+    /// Applies the transform to a point
+    pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            self.a * x + self.b * y + self.c,
+            self.d * x + self.e * y + self.f,
+        )
+    }
+
+    /// Serializes the transform as 6 little endian f32 values
+    pub fn to_bytes(&self) -> [u8; AFFINE_TRANSFORM_LEN] {
+        let mut bytes = [0u8; AFFINE_TRANSFORM_LEN];
+        let values = [self.a, self.b, self.c, self.d, self.e, self.f];
+        for (chunk, value) in bytes.chunks_exact_mut(4).zip(values.iter()) {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Deserializes a transform written by `to_bytes`, returns None if the data is not valid
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != AFFINE_TRANSFORM_LEN {
+            return None;
+        }
+
+        let mut values = [0f32; 6];
+        for (value, chunk) in values.iter_mut().zip(bytes.chunks_exact(4)) {
+            *value = f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+
+        if values.iter().any(|v| !v.is_finite()) {
+            return None;
+        }
+
+        let [a, b, c, d, e, f] = values;
+        Some(Self { a, b, c, d, e, f })
+    }
+}
+
+// Solve the 3x3 linear system m * x = rhs using Cramer's rule, None if the matrix is (nearly) singular
+fn solve3(m: &[[f64; 3]; 3], rhs: &[f64; 3]) -> Option<[f64; 3]> {
+    // The normal equations hold sums of squared pixel coordinates, compare the determinant to the cube of the
+    // largest entry instead of an absolute threshold
+    const MIN_RELATIVE_DET: f64 = 1e-10;
+    let scale = m
+        .iter()
+        .flatten()
+        .fold(0f64, |max, value| max.max(value.abs()));
+    let det = det3(m);
+    if scale == 0.0 || det.abs() <= MIN_RELATIVE_DET * scale.powi(3) {
+        return None;
+    }
+
+    let mut result = [0f64; 3];
+    for (col, value) in result.iter_mut().enumerate() {
+        let mut mc = *m;
+        for row in 0..3 {
+            mc[row][col] = rhs[row];
+        }
+        *value = det3(&mc) / det;
+    }
+
+    Some(result)
+}
+
+fn det3(m: &[[f64; 3]; 3]) -> f64 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+/// Number of targets shown during calibration
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CalibrationPoints {
+    Three,
+    Five,
+}
+
+/// The target positions for a calibration sequence on a screen of the given size
+///
+/// The targets are placed 10% in from the edges of the screen.
+pub fn calibration_targets(points: CalibrationPoints, width: u16, height: u16) -> Vec<(f32, f32)> {
+    let (w, h) = (width as f32, height as f32);
+    let (left, right) = (w * 0.1, w * 0.9);
+    let (top, bottom) = (h * 0.1, h * 0.9);
+
+    match points {
+        CalibrationPoints::Three => vec![(left, top), (right, h * 0.5), (w * 0.5, bottom)],
+        CalibrationPoints::Five => vec![
+            (left, top),
+            (right, top),
+            (right, bottom),
+            (left, bottom),
+            (w * 0.5, h * 0.5),
+        ],
+    }
+}
+
+/// Runs the on screen calibration sequence on the active screen of `display`.
+///
+/// A target is shown at each calibration position and the user touches it. `read_touch` must return the
+/// touch state without any calibration applied. The calibration fails with `CalibrationError::Timeout` if the
+/// targets are not all touched within `timeout`, so a broken touch panel does not stop the UI from starting.
+/// This must be run before the touchscreen is registered as an Lvgl input device, otherwise Lvgl and the
+/// calibration will both be reading the GT911.
+pub fn run_calibration<F>(
+    display: &Display,
+    points: CalibrationPoints,
+    width: u16,
+    height: u16,
+    timeout: Duration,
+    mut read_touch: F,
+) -> Result<AffineTransform, CalibrationError>
+where
+    F: FnMut() -> TouchState,
+{
+    const TARGET_SIZE: i32 = 30;
+
+    let deadline = Instant::now() + timeout;
+    let targets = calibration_targets(points, width, height);
+    let mut touched: Vec<(f32, f32)> = Vec::with_capacity(targets.len());
+
+    {
+        let mut screen = display.get_scr_act().unwrap();
+
+        let mut lbl = Label::create(&mut screen).unwrap();
+        lbl.set_align(Align::Center, 0, 0);
+        lbl.set_text(CString::new("Touch the targets").unwrap().as_c_str());
+
+        let mut target_style = Style::default();
+        target_style.set_bg_color(Color::from_rgb((255, 0, 0)));
+        target_style.set_radius(TARGET_SIZE as _);
+
+        let mut target = Btn::create(&mut screen).unwrap();
+        target.set_size(TARGET_SIZE as _, TARGET_SIZE as _);
+        target.add_style(Part::Main, &mut target_style);
+
+        for &(tx, ty) in targets.iter() {
+            target.set_align(
+                Align::TopLeft,
+                (tx as i32 - TARGET_SIZE / 2) as _,
+                (ty as i32 - TARGET_SIZE / 2) as _,
+            );
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let Some(tp) = wait_for_tap(&mut read_touch, remaining) else {
+                break;
+            };
+            info!(
+                "Calibration target ({}, {}) touched at ({}, {})",
+                tx, ty, tp.x, tp.y
+            );
+            touched.push((tp.x as f32, tp.y as f32));
+        }
+
+        // Remove the calibration widgets from the screen while the target style is still alive
+        // SAFETY: called from the Lvgl thread after the display is registered so there is an active screen, the
+        // label and target are not used after the screen is cleaned
+        unsafe {
+            lvgl_sys::lv_obj_clean(lvgl_sys::lv_disp_get_scr_act(core::ptr::null_mut()));
+        }
+    }
+
+    if touched.len() < targets.len() {
+        return Err(CalibrationError::Timeout);
+    }
+
+    AffineTransform::from_samples(&touched, &targets)
+}
+
+// Keep Lvgl running until a press followed by a release is detected, returns the last pressed touch point or
+// None if there was no tap within `timeout`
+fn wait_for_tap<F>(read_touch: &mut F, timeout: Duration) -> Option<TouchPoint>
+where
+    F: FnMut() -> TouchState,
+{
+    let start = Instant::now();
+    let mut pressed: Option<TouchPoint> = None;
+
+    while start.elapsed() < timeout {
+        lvgl::task_handler();
+        std::thread::sleep(Duration::from_millis(30));
+        lvgl::tick_inc(Duration::from_millis(30));
+
+        match read_touch() {
+            TouchState::PRESSED(tp) => pressed = Some(tp),
+            TouchState::RELEASED(_) => {
+                if pressed.is_some() {
+                    return pressed;
+                }
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGETS: [(f32, f32); 5] = [
+        (80.0, 48.0),
+        (720.0, 48.0),
+        (720.0, 432.0),
+        (80.0, 432.0),
+        (400.0, 240.0),
+    ];
+
+    fn assert_close(actual: (f32, f32), expected: (f32, f32)) {
+        assert!(
+            (actual.0 - expected.0).abs() < 0.01 && (actual.1 - expected.1).abs() < 0.01,
+            "{:?} is not {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn identity_fit() {
+        let transform = AffineTransform::from_samples(&TARGETS, &TARGETS).unwrap();
+        for &(x, y) in TARGETS.iter() {
+            assert_close(transform.apply(x, y), (x, y));
+        }
+        assert_close(transform.apply(0.0, 0.0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn scale_and_offset_fit() {
+        // The touch panel reports 0.9 times the screen coordinate plus an offset
+        let touched: Vec<(f32, f32)> = TARGETS
+            .iter()
+            .map(|&(x, y)| (x * 0.9 + 10.0, y * 1.1 - 5.0))
+            .collect();
+
+        let transform = AffineTransform::from_samples(&touched, &TARGETS).unwrap();
+        assert!((transform.a - 1.0 / 0.9).abs() < 1e-4);
+        assert!((transform.e - 1.0 / 1.1).abs() < 1e-4);
+        assert!(transform.b.abs() < 1e-4 && transform.d.abs() < 1e-4);
+        for (&(tx, ty), &target) in touched.iter().zip(TARGETS.iter()) {
+            assert_close(transform.apply(tx, ty), target);
+        }
+    }
+
+    #[test]
+    fn rotated_fit() {
+        // The touch panel is rotated 90 degrees and offset, with three samples the fit is exact
+        let touched: Vec<(f32, f32)> = TARGETS.iter().map(|&(x, y)| (y, 800.0 - x)).collect();
+
+        let transform = AffineTransform::from_samples(&touched[..3], &TARGETS[..3]).unwrap();
+        for (&(tx, ty), &target) in touched.iter().zip(TARGETS.iter()) {
+            assert_close(transform.apply(tx, ty), target);
+        }
+    }
+
+    #[test]
+    fn least_squares_spreads_the_error() {
+        let mut touched = TARGETS.to_vec();
+        touched[4].0 += 5.0;
+
+        let transform = AffineTransform::from_samples(&touched, &TARGETS).unwrap();
+        let (x, _) = transform.apply(touched[4].0, touched[4].1);
+        assert!(x > 400.0 && x < 405.0, "{}", x);
+    }
+
+    #[test]
+    fn collinear_samples_fail() {
+        let touched = [(0.0, 0.0), (100.0, 100.0), (200.0, 200.0), (300.0, 300.0)];
+        assert_eq!(
+            AffineTransform::from_samples(&touched, &TARGETS[..4]),
+            Err(CalibrationError::Degenerate)
+        );
+
+        // Collinear up to the f32 rounding of y = x / 3, the determinant is far from 0 in absolute terms
+        let rounded = [
+            (100.0, 100.0 / 3.0),
+            (400.0, 400.0 / 3.0),
+            (700.0, 700.0 / 3.0),
+            (790.0, 790.0 / 3.0),
+        ];
+        assert_eq!(
+            AffineTransform::from_samples(&rounded, &TARGETS[..4]),
+            Err(CalibrationError::Degenerate)
+        );
+
+        let same = [(50.0, 50.0); 3];
+        assert_eq!(
+            AffineTransform::from_samples(&same, &TARGETS[..3]),
+            Err(CalibrationError::Degenerate)
+        );
+
+        assert_eq!(
+            AffineTransform::from_samples(&TARGETS[..2], &TARGETS[..2]),
+            Err(CalibrationError::NotEnoughSamples(2))
+        );
+    }
+
+    #[test]
+    fn solve3_cramers_rule() {
+        // 2x + y = 5, x + 3y + z = 12.5, y + 4z = 13 has the solution x = 1, y = 3, z = 2.5
+        let m = [[2.0, 1.0, 0.0], [1.0, 3.0, 1.0], [0.0, 1.0, 4.0]];
+        let [x, y, z] = solve3(&m, &[5.0, 12.5, 13.0]).unwrap();
+        assert!((x - 1.0).abs() < 1e-9 && (y - 3.0).abs() < 1e-9 && (z - 2.5).abs() < 1e-9);
+
+        let singular = [[1.0, 2.0, 3.0], [2.0, 4.0, 6.0], [0.0, 1.0, 1.0]];
+        assert_eq!(solve3(&singular, &[1.0, 2.0, 3.0]), None);
+
+        // Small entries are fine as long as the matrix is well conditioned
+        let small = [[2e-6, 1e-6, 0.0], [1e-6, 3e-6, 1e-6], [0.0, 1e-6, 4e-6]];
+        assert!(solve3(&small, &[5e-6, 12.5e-6, 13e-6]).is_some());
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let transform = AffineTransform::from_samples(&TARGETS, &TARGETS).unwrap();
+        assert_eq!(
+            AffineTransform::from_bytes(&transform.to_bytes()),
+            Some(transform)
+        );
+        assert_eq!(AffineTransform::from_bytes(&[0; 4]), None);
+    }
+}
@@ -0,0 +1,356 @@
+//! Display backends Lvgl flushes into.
+//!
+//! `flush` copies the area refreshed by Lvgl into any `DisplayBackend` so the same Lvgl setup drives the LCD
+//! panel, the in-memory framebuffer used by the simulator and tests, or any embedded-graphics display through
+//! `DrawTargetBackend`.
+use core::mem::{align_of, size_of};
+
+use embedded_graphics_core::draw_target::DrawTarget;
+use embedded_graphics_core::geometry::{OriginDimensions, Point, Size};
+use embedded_graphics_core::pixelcolor::raw::RawData;
+use embedded_graphics_core::pixelcolor::{PixelColor, Rgb565};
+use embedded_graphics_core::primitives::Rectangle;
+use lvgl::DisplayRefresh;
+
+use crate::panel_config::ColorMode;
+
+// The Lvgl draw buffer is passed to the backends as Rgb565 pixels and the backends convert them to their color
+// format, Lvgl must render RGB565 without swapping the bytes
+const _: () = assert!(
+    lvgl_sys::LV_COLOR_DEPTH == 16 && lvgl_sys::LV_COLOR_16_SWAP == 0,
+    "set LV_COLOR_DEPTH to 16 and LV_COLOR_16_SWAP to 0 in lv_conf.h, the flush converts RGB565 to the panel color mode"
+);
+const _: () = assert!(
+    size_of::<lvgl::Color>() == size_of::<Rgb565>()
+        && align_of::<lvgl::Color>() == align_of::<Rgb565>()
+);
+
+/// The color format of the pixels stored or sent by a display
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ColorFormat {
+    /// 16 bit RGB565
+    Rgb565,
+    /// 16 bit RGB565 with the two bytes swapped
+    Rgb565Swapped,
+    /// 24 bit RGB888
+    Rgb888,
+    /// Any other embedded-graphics color
+    Other { bits_per_pixel: usize },
+}
+
+impl From<ColorMode> for ColorFormat {
+    fn from(color_mode: ColorMode) -> Self {
+        match color_mode {
+            ColorMode::Rgb565 => ColorFormat::Rgb565,
+            ColorMode::Rgb565Swapped => ColorFormat::Rgb565Swapped,
+            ColorMode::Rgb888 => ColorFormat::Rgb888,
+        }
+    }
+}
+
+/// A display Lvgl can flush into
+pub trait DisplayBackend {
+    type Error;
+
+    /// The width and height in pixels of the display as drawn by Lvgl
+    fn resolution(&self) -> (u32, u32);
+
+    /// The color format of the display, the backend converts the RGB565 pixels from Lvgl to this format
+    fn color_format(&self) -> ColorFormat;
+
+    /// Draws the pixels of a rectangular region.
+    ///
+    /// The region is `sx..ex` by `sy..ey` (the end coordinates are exclusive) and `pixels` holds the pixels of
+    /// the region row first starting at the top left corner.
+    fn draw_region(
+        &mut self,
+        sx: i32,
+        sy: i32,
+        ex: i32,
+        ey: i32,
+        pixels: &[Rgb565],
+    ) -> Result<(), Self::Error>;
+
+    /// Called after the last region of an Lvgl refresh has been drawn, backends that buffer the drawn regions
+    /// show them here.
+    fn flush_ready(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Draws the area refreshed by Lvgl into `backend`, call this from the `Display::register` callback.
+pub fn flush<B: DisplayBackend, const N: usize>(
+    backend: &mut B,
+    refresh: &DisplayRefresh<N>,
+) -> Result<(), B::Error> {
+    // Only the start of the draw buffer holds the colors of the refreshed area
+    let area = &refresh.area;
+    let len = ((area.x2 - area.x1 + 1) as usize * (area.y2 - area.y1 + 1) as usize)
+        .min(refresh.colors.len());
+    let pixels =
+        unsafe { core::slice::from_raw_parts(refresh.colors.as_ptr() as *const Rgb565, len) };
+
+    // Lvgl flushes a refresh in several areas when the draw buffer is smaller than the screen
+    let last = unsafe {
+        let disp = lvgl_sys::_lv_refr_get_disp_refreshing();
+        disp.is_null() || lvgl_sys::lv_disp_flush_is_last((*disp).driver)
+    };
+
+    flush_region(
+        backend,
+        area.x1.into(),
+        area.y1.into(),
+        (area.x2 + 1i16).into(),
+        (area.y2 + 1i16).into(),
+        pixels,
+        last,
+    )
+}
+
+/// Draws the pixels of a region refreshed by Lvgl into `backend`, `last` is true for the last region of the
+/// refresh. For flush callbacks that get the draw buffer straight from Lvgl.
+pub fn flush_region<B: DisplayBackend>(
+    backend: &mut B,
+    sx: i32,
+    sy: i32,
+    ex: i32,
+    ey: i32,
+    pixels: &[Rgb565],
+    last: bool,
+) -> Result<(), B::Error> {
+    backend.draw_region(sx, sy, ex, ey, pixels)?;
+    if last {
+        backend.flush_ready()?;
+    }
+
+    Ok(())
+}
+
+/// Adapts an embedded-graphics `DrawTarget` into a `DisplayBackend`
+pub struct DrawTargetBackend<D> {
+    target: D,
+}
+
+impl<D> DrawTargetBackend<D> {
+    pub fn new(target: D) -> Self {
+        Self { target }
+    }
+
+    pub fn target(&self) -> &D {
+        &self.target
+    }
+
+    pub fn target_mut(&mut self) -> &mut D {
+        &mut self.target
+    }
+
+    pub fn into_inner(self) -> D {
+        self.target
+    }
+}
+
+impl<D> DisplayBackend for DrawTargetBackend<D>
+where
+    D: DrawTarget + OriginDimensions,
+    D::Color: From<Rgb565>,
+{
+    type Error = D::Error;
+
+    fn resolution(&self) -> (u32, u32) {
+        let size = self.target.size();
+        (size.width, size.height)
+    }
+
+    fn color_format(&self) -> ColorFormat {
+        match <<D::Color as PixelColor>::Raw as RawData>::BITS_PER_PIXEL {
+            16 => ColorFormat::Rgb565,
+            24 => ColorFormat::Rgb888,
+            bits_per_pixel => ColorFormat::Other { bits_per_pixel },
+        }
+    }
+
+    fn draw_region(
+        &mut self,
+        sx: i32,
+        sy: i32,
+        ex: i32,
+        ey: i32,
+        pixels: &[Rgb565],
+    ) -> Result<(), Self::Error> {
+        let size = Size::new((ex - sx).max(0) as u32, (ey - sy).max(0) as u32);
+        let area = Rectangle::new(Point::new(sx, sy), size);
+        let len = (size.width * size.height) as usize;
+
+        self.target.fill_contiguous(
+            &area,
+            pixels[..len.min(pixels.len())]
+                .iter()
+                .map(|pixel| D::Color::from(*pixel)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::Infallible;
+
+    use embedded_graphics_core::pixelcolor::{Rgb888, RgbColor};
+    use embedded_graphics_core::Pixel;
+    use lvgl::{Area, Color};
+
+    use super::*;
+    use crate::framebuffer::Framebuffer;
+
+    // Records the regions drawn and the number of flush_ready calls
+    #[derive(Default)]
+    struct Recorder {
+        regions: Vec<(i32, i32, i32, i32, usize)>,
+        flush_ready: usize,
+    }
+
+    impl DisplayBackend for Recorder {
+        type Error = Infallible;
+
+        fn resolution(&self) -> (u32, u32) {
+            (4, 3)
+        }
+
+        fn color_format(&self) -> ColorFormat {
+            ColorFormat::Rgb565
+        }
+
+        fn draw_region(
+            &mut self,
+            sx: i32,
+            sy: i32,
+            ex: i32,
+            ey: i32,
+            pixels: &[Rgb565],
+        ) -> Result<(), Self::Error> {
+            self.regions.push((sx, sy, ex, ey, pixels.len()));
+            Ok(())
+        }
+
+        fn flush_ready(&mut self) -> Result<(), Self::Error> {
+            self.flush_ready += 1;
+            Ok(())
+        }
+    }
+
+    // An embedded-graphics RGB888 display
+    struct Rgb888Display {
+        size: Size,
+        pixels: Vec<Rgb888>,
+    }
+
+    impl Rgb888Display {
+        fn new(width: u32, height: u32) -> Self {
+            Self {
+                size: Size::new(width, height),
+                pixels: vec![Rgb888::BLACK; (width * height) as usize],
+            }
+        }
+    }
+
+    impl OriginDimensions for Rgb888Display {
+        fn size(&self) -> Size {
+            self.size
+        }
+    }
+
+    impl DrawTarget for Rgb888Display {
+        type Color = Rgb888;
+        type Error = Infallible;
+
+        fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+        where
+            I: IntoIterator<Item = Pixel<Rgb888>>,
+        {
+            for Pixel(point, color) in pixels {
+                if point.x >= 0
+                    && point.y >= 0
+                    && (point.x as u32) < self.size.width
+                    && (point.y as u32) < self.size.height
+                {
+                    self.pixels[(point.y as u32 * self.size.width + point.x as u32) as usize] =
+                        color;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    // A 2x2 refresh at (1, 1) in a draw buffer of 8 pixels, the pixels after the area are not part of it
+    fn refresh() -> DisplayRefresh<8> {
+        let white = Color::from_rgb((255, 255, 255));
+        let red = Color::from_rgb((255, 0, 0));
+        DisplayRefresh {
+            area: Area {
+                x1: 1,
+                y1: 1,
+                x2: 2,
+                y2: 2,
+            },
+            colors: [white, white, white, white, red, red, red, red],
+        }
+    }
+
+    #[test]
+    fn flush_draws_refreshed_area() {
+        // No Lvgl refresh is in progress so the area is the last one
+        let mut recorder = Recorder::default();
+        flush(&mut recorder, &refresh()).unwrap();
+        assert_eq!(recorder.regions, vec![(1, 1, 3, 3, 4)]);
+        assert_eq!(recorder.flush_ready, 1);
+
+        let mut framebuffer = Framebuffer::new(4, 3);
+        flush(&mut framebuffer, &refresh()).unwrap();
+        for y in 0..3 {
+            for x in 0..4 {
+                let expected = if (1..3).contains(&x) && (1..3).contains(&y) {
+                    Rgb565::WHITE
+                } else {
+                    Rgb565::BLACK
+                };
+                assert_eq!(framebuffer.pixel(x, y), Some(expected), "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn flush_region_ready_on_last() {
+        let mut recorder = Recorder::default();
+        let pixels = [Rgb565::RED; 8];
+        flush_region(&mut recorder, 0, 0, 4, 2, &pixels, false).unwrap();
+        assert_eq!(recorder.flush_ready, 0);
+        flush_region(&mut recorder, 0, 2, 4, 3, &pixels[..4], true).unwrap();
+        assert_eq!(recorder.flush_ready, 1);
+        assert_eq!(recorder.regions, vec![(0, 0, 4, 2, 8), (0, 2, 4, 3, 4)]);
+    }
+
+    #[test]
+    fn draw_target_backend_matches_framebuffer() {
+        let mut backend = DrawTargetBackend::new(Rgb888Display::new(4, 3));
+        assert_eq!(backend.resolution(), (4, 3));
+        assert_eq!(backend.color_format(), ColorFormat::Rgb888);
+        let mut framebuffer = Framebuffer::new(4, 3);
+
+        // The second region is partly outside of the display
+        let pixels: Vec<Rgb565> = (0..6u8)
+            .map(|i| Rgb565::new(i * 5, i * 10, 31 - i * 5))
+            .collect();
+        for (sx, sy, ex, ey) in [(0, 0, 3, 2), (2, 1, 5, 3)] {
+            flush_region(&mut backend, sx, sy, ex, ey, &pixels, true).unwrap();
+            flush_region(&mut framebuffer, sx, sy, ex, ey, &pixels, true).unwrap();
+        }
+        flush(&mut backend, &refresh()).unwrap();
+        flush(&mut framebuffer, &refresh()).unwrap();
+
+        let expected: Vec<Rgb888> = framebuffer
+            .pixels()
+            .iter()
+            .map(|p| Rgb888::from(*p))
+            .collect();
+        assert_eq!(backend.into_inner().pixels, expected);
+    }
+}
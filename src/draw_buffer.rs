@@ -0,0 +1,372 @@
+//! The Lvgl draw buffers.
+//!
+//! Lvgl renders a band of `lines` lines into a draw buffer and flushes the band to the display. More lines means
+//! fewer flushes per refresh and a faster redraw, at the cost of memory. The buffers are allocated on the heap,
+//! either in internal SRAM that DMA can read or in PSRAM which is slower but has room for many more lines. With
+//! two buffers Lvgl renders into one buffer while the other is being flushed, this only saves time when the flush
+//! returns before the pixels have been sent.
+//!
+//! The lvgl-rs `DrawBuffer` is a fixed size array and every flush through the lvgl-rs `Display::register` closure
+//! copies it onto the stack, so `register` registers the Lvgl display driver itself with the configured buffers
+//! and flushes them to a `DisplayBackend` without copying.
+use core::fmt;
+
+#[cfg(target_os = "espidf")]
+use core::cell::RefCell;
+#[cfg(target_os = "espidf")]
+use core::ffi::c_void;
+#[cfg(target_os = "espidf")]
+use core::ptr::null_mut;
+
+#[cfg(target_os = "espidf")]
+use embedded_graphics_core::pixelcolor::Rgb565;
+#[cfg(target_os = "espidf")]
+use log::*;
+
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::sys::{
+    heap_caps_free, heap_caps_malloc, EspError, ESP_ERR_NO_MEM, MALLOC_CAP_8BIT, MALLOC_CAP_DMA,
+    MALLOC_CAP_INTERNAL, MALLOC_CAP_SPIRAM,
+};
+
+#[cfg(target_os = "espidf")]
+use crate::display_backend::{self, DisplayBackend};
+
+/// Bytes per pixel of the draw buffers, Lvgl renders RGB565
+pub const BYTES_PER_PIXEL: usize = 2;
+
+/// The memory the draw buffers are allocated in
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum BufferPlacement {
+    /// Internal SRAM that DMA can read, the fastest but there is little of it
+    #[default]
+    InternalDma,
+    /// External PSRAM
+    Psram,
+}
+
+impl fmt::Display for BufferPlacement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BufferPlacement::InternalDma => write!(f, "internal DMA SRAM"),
+            BufferPlacement::Psram => write!(f, "PSRAM"),
+        }
+    }
+}
+
+/// Draw buffer configuration
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DrawBufferConfig {
+    /// Number of lines (rows) rendered at a time, limited to the height of the display
+    pub lines: u32,
+    /// Use two buffers so Lvgl renders into one while the other is flushed
+    pub double_buffer: bool,
+    pub placement: BufferPlacement,
+    /// Use the other memory if the buffers do not fit in `placement`
+    pub fallback: bool,
+}
+
+impl DrawBufferConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn lines(mut self, lines: u32) -> Self {
+        self.lines = lines;
+        self
+    }
+
+    #[must_use]
+    pub fn double_buffer(mut self, double_buffer: bool) -> Self {
+        self.double_buffer = double_buffer;
+        self
+    }
+
+    #[must_use]
+    pub fn placement(mut self, placement: BufferPlacement) -> Self {
+        self.placement = placement;
+        self
+    }
+
+    #[must_use]
+    pub fn fallback(mut self, fallback: bool) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Number of lines in a buffer for a display `ver_res` lines high, at least 1
+    pub fn buffer_lines(&self, ver_res: u32) -> u32 {
+        self.lines.clamp(1, ver_res.max(1))
+    }
+
+    /// Number of pixels in a buffer
+    pub fn buffer_len(&self, hor_res: u32, ver_res: u32) -> usize {
+        hor_res as usize * self.buffer_lines(ver_res) as usize
+    }
+
+    pub fn buffer_count(&self) -> usize {
+        if self.double_buffer {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Bytes used by all the buffers
+    pub fn total_bytes(&self, hor_res: u32, ver_res: u32) -> usize {
+        self.buffer_len(hor_res, ver_res) * BYTES_PER_PIXEL * self.buffer_count()
+    }
+}
+
+impl Default for DrawBufferConfig {
+    fn default() -> Self {
+        Self {
+            lines: 40,
+            double_buffer: false,
+            placement: BufferPlacement::InternalDma,
+            fallback: true,
+        }
+    }
+}
+
+/// The draw buffers allocated by `register`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DrawBufferInfo {
+    pub lines: u32,
+    pub buffer_count: usize,
+    pub bytes_per_buffer: usize,
+    /// Where the buffers were allocated, differs from the configured placement after a fallback
+    pub placement: BufferPlacement,
+}
+
+impl DrawBufferInfo {
+    pub fn total_bytes(&self) -> usize {
+        self.bytes_per_buffer * self.buffer_count
+    }
+}
+
+impl fmt::Display for DrawBufferInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} x {} bytes ({} lines) in {}",
+            self.buffer_count, self.bytes_per_buffer, self.lines, self.placement
+        )
+    }
+}
+
+/// Registers an Lvgl display that renders into the draw buffers described by `config` and flushes them to
+/// `backend`. The display is the size of `backend` and becomes the default display when it is the first display
+/// registered, get it with `lvgl::Display::default()`.
+///
+/// Must be called after `lvgl::init` and before the first `lvgl::task_handler`.
+///
+/// # Safety
+///
+/// The Lvgl display keeps a pointer to `backend` and calls the backend from `lvgl::task_handler`, `backend` must
+/// outlive the Lvgl display and must not be moved while the display exists.
+#[cfg(target_os = "espidf")]
+pub unsafe fn register<B>(
+    config: &DrawBufferConfig,
+    backend: &RefCell<B>,
+) -> Result<DrawBufferInfo, EspError>
+where
+    B: DisplayBackend,
+    B::Error: fmt::Display,
+{
+    let (hor_res, ver_res) = backend.borrow().resolution();
+    let len = config.buffer_len(hor_res, ver_res);
+    let bytes = len * BYTES_PER_PIXEL;
+    let count = config.buffer_count();
+
+    let mut placement = config.placement;
+    let mut buffers = alloc_buffers(bytes, count, placement);
+    if buffers.is_none() && config.fallback {
+        let other = match placement {
+            BufferPlacement::InternalDma => BufferPlacement::Psram,
+            BufferPlacement::Psram => BufferPlacement::InternalDma,
+        };
+        warn!(
+            "Not enough {} for {} x {} bytes of draw buffer, using {}",
+            placement, count, bytes, other
+        );
+        placement = other;
+        buffers = alloc_buffers(bytes, count, placement);
+    }
+    let Some([buf1, buf2]) = buffers else {
+        warn!(
+            "Not enough {} for {} x {} bytes of draw buffer",
+            placement, count, bytes
+        );
+        return Err(EspError::from_infallible::<ESP_ERR_NO_MEM>());
+    };
+
+    let user_data = backend as *const RefCell<B> as *mut c_void;
+    if let Err(e) = register_driver(
+        (hor_res, ver_res),
+        [buf1, buf2],
+        len as u32,
+        flush_cb::<B>,
+        user_data,
+        false,
+    ) {
+        heap_caps_free(buf1);
+        heap_caps_free(buf2);
+        return Err(e);
+    }
+
+    Ok(DrawBufferInfo {
+        lines: config.buffer_lines(ver_res),
+        buffer_count: count,
+        bytes_per_buffer: bytes,
+        placement,
+    })
+}
+
+/// The Lvgl display flush callback
+#[cfg(target_os = "espidf")]
+pub(crate) type FlushCb = unsafe extern "C" fn(
+    *mut lvgl_sys::lv_disp_drv_t,
+    *const lvgl_sys::lv_area_t,
+    *mut lvgl_sys::lv_color_t,
+);
+
+/// Registers an Lvgl display driver of `resolution` pixels that renders into `buffers` (`buf_len` pixels each,
+/// the second buffer may be null) and calls `flush_cb` with `user_data` set in the driver.
+///
+/// The `lv_disp_drv_t` and the `lv_disp_draw_buf_t` are leaked. Lvgl keeps pointers to both in the display and
+/// nothing removes the display (`lv_disp_remove`), so they are owned by Lvgl and live for the rest of the
+/// program, like the display itself. The buffers must live as long.
+#[cfg(target_os = "espidf")]
+pub(crate) unsafe fn register_driver(
+    resolution: (u32, u32),
+    buffers: [*mut c_void; 2],
+    buf_len: u32,
+    flush_cb: FlushCb,
+    user_data: *mut c_void,
+    full_refresh: bool,
+) -> Result<(), EspError> {
+    let draw_buf: &'static mut lvgl_sys::lv_disp_draw_buf_t =
+        Box::leak(Box::new(core::mem::zeroed()));
+    lvgl_sys::lv_disp_draw_buf_init(draw_buf, buffers[0], buffers[1], buf_len);
+
+    let drv: &'static mut lvgl_sys::lv_disp_drv_t = Box::leak(Box::new(core::mem::zeroed()));
+    lvgl_sys::lv_disp_drv_init(drv);
+    drv.hor_res = resolution.0 as lvgl_sys::lv_coord_t;
+    drv.ver_res = resolution.1 as lvgl_sys::lv_coord_t;
+    drv.draw_buf = draw_buf;
+    drv.flush_cb = Some(flush_cb);
+    drv.user_data = user_data;
+    drv.set_full_refresh(full_refresh.into());
+
+    // Lvgl only fails to register the display when it runs out of memory
+    if lvgl_sys::lv_disp_drv_register(drv).is_null() {
+        warn!("Failed to register the Lvgl display");
+        return Err(EspError::from_infallible::<ESP_ERR_NO_MEM>());
+    }
+
+    Ok(())
+}
+
+// Allocate `count` buffers of `bytes` bytes, the second buffer is null with a single buffer
+#[cfg(target_os = "espidf")]
+unsafe fn alloc_buffers(
+    bytes: usize,
+    count: usize,
+    placement: BufferPlacement,
+) -> Option<[*mut c_void; 2]> {
+    let caps = match placement {
+        BufferPlacement::InternalDma => MALLOC_CAP_DMA | MALLOC_CAP_INTERNAL,
+        BufferPlacement::Psram => MALLOC_CAP_SPIRAM | MALLOC_CAP_8BIT,
+    };
+
+    // heap_caps_free ignores the null buffers
+    let buffers: [*mut c_void; 2] = core::array::from_fn(|i| {
+        if i < count {
+            heap_caps_malloc(bytes, caps)
+        } else {
+            null_mut()
+        }
+    });
+    if buffers[..count].iter().any(|buffer| buffer.is_null()) {
+        for buffer in buffers {
+            heap_caps_free(buffer);
+        }
+        return None;
+    }
+
+    Some(buffers)
+}
+
+// Draw the rendered area into the backend, the draw buffer is free again when this returns
+#[cfg(target_os = "espidf")]
+unsafe extern "C" fn flush_cb<B>(
+    drv: *mut lvgl_sys::lv_disp_drv_t,
+    area: *const lvgl_sys::lv_area_t,
+    color_p: *mut lvgl_sys::lv_color_t,
+) where
+    B: DisplayBackend,
+    B::Error: fmt::Display,
+{
+    let backend = &*((*drv).user_data as *const RefCell<B>);
+    let area = &*area;
+    let (sx, sy) = (i32::from(area.x1), i32::from(area.y1));
+    let (ex, ey) = (i32::from(area.x2) + 1, i32::from(area.y2) + 1);
+
+    // Lvgl stores the rendered area at the start of the draw buffer
+    let len = ((ex - sx).max(0) * (ey - sy).max(0)) as usize;
+    let pixels = core::slice::from_raw_parts(color_p as *const Rgb565, len);
+    let last = lvgl_sys::lv_disp_flush_is_last(drv);
+
+    let result =
+        display_backend::flush_region(&mut *backend.borrow_mut(), sx, sy, ex, ey, pixels, last);
+    if let Err(e) = result {
+        warn!("Failed to flush draw buffer: {}", e);
+    }
+
+    lvgl_sys::lv_disp_flush_ready(drv);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_lines() {
+        let config = DrawBufferConfig::new();
+        assert_eq!(config.buffer_lines(480), 40);
+        // Limited to the height of the display and at least 1 line
+        assert_eq!(config.lines(1000).buffer_lines(480), 480);
+        assert_eq!(config.lines(0).buffer_lines(480), 1);
+        assert_eq!(config.buffer_lines(0), 1);
+    }
+
+    #[test]
+    fn buffer_len_and_total_bytes() {
+        let config = DrawBufferConfig::new();
+        assert_eq!(config.buffer_count(), 1);
+        assert_eq!(config.buffer_len(800, 480), 800 * 40);
+        assert_eq!(config.total_bytes(800, 480), 64000);
+
+        let config = config.double_buffer(true);
+        assert_eq!(config.buffer_count(), 2);
+        assert_eq!(config.total_bytes(800, 480), 2 * 64000);
+
+        // A rotated display renders bands of the rotated width
+        assert_eq!(config.buffer_len(480, 800), 480 * 40);
+        assert_eq!(config.lines(1000).total_bytes(800, 480), 2 * 800 * 480 * 2);
+    }
+
+    #[test]
+    fn draw_buffer_info() {
+        let info = DrawBufferInfo {
+            lines: 40,
+            buffer_count: 2,
+            bytes_per_buffer: 64000,
+            placement: BufferPlacement::Psram,
+        };
+        assert_eq!(info.total_bytes(), 128000);
+        assert_eq!(info.to_string(), "2 x 64000 bytes (40 lines) in PSRAM");
+    }
+}
@@ -0,0 +1,142 @@
+//! An in-memory RGB565 framebuffer.
+//!
+//! Used in place of the LCD panel by the desktop simulator, the framebuffer can be written out as a PPM image.
+use core::convert::Infallible;
+use std::io::{self, Write};
+
+use embedded_graphics_core::pixelcolor::{Rgb565, RgbColor};
+
+use crate::display_backend::{ColorFormat, DisplayBackend};
+
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<Rgb565>,
+}
+
+impl Framebuffer {
+    /// Creates a framebuffer with all pixels black
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Rgb565::BLACK; (width * height) as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// All the pixels, row first starting at the top left corner
+    pub fn pixels(&self) -> &[Rgb565] {
+        &self.pixels
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Option<Rgb565> {
+        if x < self.width && y < self.height {
+            Some(self.pixels[(y * self.width + x) as usize])
+        } else {
+            None
+        }
+    }
+
+    ///
+    /// Sets pixel colors in a rectangular region.
+    ///
+    /// Same as `LcdPanel::set_pixels_lvgl_color`, the end coordinates are exclusive. Pixels outside of the
+    /// framebuffer are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `sx` - x coordinate start
+    /// * `sy` - y coordinate start
+    /// * `ex` - x coordinate end
+    /// * `ey` - y coordinate end
+    /// * `colors` - anything that can provide `IntoIterator<Item = lvgl::Color>` to iterate over pixel data
+    pub fn set_pixels_lvgl_color<T>(&mut self, sx: i32, sy: i32, ex: i32, ey: i32, colors: T)
+    where
+        T: IntoIterator<Item = lvgl::Color>,
+    {
+        let width = (ex - sx).max(0) as usize;
+        if width == 0 {
+            return;
+        }
+
+        let mut colors = colors.into_iter();
+        for y in sy..ey {
+            for (x, color) in (sx..ex).zip(colors.by_ref().take(width)) {
+                if x >= 0 && y >= 0 && (x as u32) < self.width && (y as u32) < self.height {
+                    self.pixels[(y as u32 * self.width + x as u32) as usize] = Rgb565::from(color);
+                }
+            }
+        }
+    }
+
+    /// Writes the framebuffer as a binary (P6) PPM image
+    pub fn write_ppm<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+
+        let mut row = Vec::with_capacity(self.width as usize * 3);
+        for line in self.pixels.chunks_exact(self.width as usize) {
+            row.clear();
+            for pixel in line {
+                row.extend_from_slice(&rgb565_to_rgb888(*pixel));
+            }
+            writer.write_all(&row)?;
+        }
+
+        writer.flush()
+    }
+}
+
+impl DisplayBackend for Framebuffer {
+    type Error = Infallible;
+
+    fn resolution(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn color_format(&self) -> ColorFormat {
+        ColorFormat::Rgb565
+    }
+
+    // Pixels outside of the framebuffer are ignored
+    fn draw_region(
+        &mut self,
+        sx: i32,
+        sy: i32,
+        ex: i32,
+        ey: i32,
+        pixels: &[Rgb565],
+    ) -> Result<(), Self::Error> {
+        let width = (ex - sx).max(0) as usize;
+        if width == 0 {
+            return Ok(());
+        }
+
+        for (y, line) in (sy..ey).zip(pixels.chunks(width)) {
+            for (x, pixel) in (sx..ex).zip(line) {
+                if x >= 0 && y >= 0 && (x as u32) < self.width && (y as u32) < self.height {
+                    self.pixels[(y as u32 * self.width + x as u32) as usize] = *pixel;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Expands a RGB565 color to 8 bits per channel
+pub fn rgb565_to_rgb888(color: Rgb565) -> [u8; 3] {
+    let (r, g, b) = (color.r(), color.g(), color.b());
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+    ]
+}
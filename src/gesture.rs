@@ -0,0 +1,498 @@
+//! Gesture recognizer for the GT911 touch stream.
+//!
+//! The recognizer takes timestamped touch samples (all the touch points from `GT911::read_touches` or the single
+//! point `TouchState` used by Lvgl) and emits swipe, long press, double tap, pinch and rotate gestures.
+//! It does not depend on any hardware so it can be run on recorded touch traces.
+use core::f32::consts::PI;
+use core::time::Duration;
+
+use crate::gt911::{TouchPoint, TouchState};
+
+/// Direction of a swipe
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SwipeDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// A recognized gesture
+///
+/// - `Pinch` scale is relative to the finger distance when the second finger touched the screen
+/// - `Rotate` angle is in radians relative to the finger angle when the second finger touched the screen,
+///   positive is clockwise on the screen
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Gesture {
+    Swipe(SwipeDirection),
+    LongPress {
+        x: u16,
+        y: u16,
+    },
+    DoubleTap {
+        x: u16,
+        y: u16,
+    },
+    Pinch {
+        scale: f32,
+        center_x: u16,
+        center_y: u16,
+    },
+    Rotate {
+        angle: f32,
+        center_x: u16,
+        center_y: u16,
+    },
+}
+
+/// Thresholds used to recognize the gestures
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GestureConfig {
+    pub swipe_min_distance: u16,
+    pub swipe_max_duration: Duration,
+    pub long_press_duration: Duration,
+    pub tap_max_movement: u16,
+    pub tap_max_duration: Duration,
+    pub double_tap_max_interval: Duration,
+    pub double_tap_max_distance: u16,
+    pub pinch_min_scale_change: f32,
+    pub rotate_min_angle: f32,
+}
+
+impl GestureConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn swipe_min_distance(mut self, distance: u16) -> Self {
+        self.swipe_min_distance = distance;
+        self
+    }
+
+    #[must_use]
+    pub fn swipe_max_duration(mut self, duration: Duration) -> Self {
+        self.swipe_max_duration = duration;
+        self
+    }
+
+    #[must_use]
+    pub fn long_press_duration(mut self, duration: Duration) -> Self {
+        self.long_press_duration = duration;
+        self
+    }
+
+    #[must_use]
+    pub fn tap_max_movement(mut self, distance: u16) -> Self {
+        self.tap_max_movement = distance;
+        self
+    }
+
+    #[must_use]
+    pub fn tap_max_duration(mut self, duration: Duration) -> Self {
+        self.tap_max_duration = duration;
+        self
+    }
+
+    #[must_use]
+    pub fn double_tap_max_interval(mut self, interval: Duration) -> Self {
+        self.double_tap_max_interval = interval;
+        self
+    }
+
+    #[must_use]
+    pub fn double_tap_max_distance(mut self, distance: u16) -> Self {
+        self.double_tap_max_distance = distance;
+        self
+    }
+
+    #[must_use]
+    pub fn pinch_min_scale_change(mut self, scale_change: f32) -> Self {
+        self.pinch_min_scale_change = scale_change;
+        self
+    }
+
+    #[must_use]
+    pub fn rotate_min_angle(mut self, angle: f32) -> Self {
+        self.rotate_min_angle = angle;
+        self
+    }
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            swipe_min_distance: 80,
+            swipe_max_duration: Duration::from_millis(500),
+            long_press_duration: Duration::from_millis(600),
+            tap_max_movement: 15,
+            tap_max_duration: Duration::from_millis(250),
+            double_tap_max_interval: Duration::from_millis(300),
+            double_tap_max_distance: 30,
+            pinch_min_scale_change: 0.05,
+            rotate_min_angle: 0.05,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum State {
+    Idle,
+    OneFinger {
+        start_time: Duration,
+        start: (f32, f32),
+        last: (f32, f32),
+        long_press: bool,
+    },
+    TwoFingers {
+        start_distance: f32,
+        start_angle: f32,
+        last_scale: f32,
+        last_angle: f32,
+    },
+    // A second finger was on the screen, wait until all fingers are lifted before recognizing a new gesture
+    WaitRelease,
+}
+
+/// Recognizes gestures from a stream of timestamped touch samples
+pub struct GestureRecognizer {
+    config: GestureConfig,
+    state: State,
+    last_tap: Option<(Duration, (f32, f32))>,
+}
+
+impl GestureRecognizer {
+    pub fn new(config: GestureConfig) -> Self {
+        Self {
+            config,
+            state: State::Idle,
+            last_tap: None,
+        }
+    }
+
+    /// Feed the single touch point state used by Lvgl, pinch and rotate are never recognized from this state
+    pub fn update_state(&mut self, timestamp: Duration, state: TouchState) -> Vec<Gesture> {
+        match state {
+            TouchState::PRESSED(tp) => self.update(timestamp, &[tp]),
+            TouchState::RELEASED(_) => self.update(timestamp, &[]),
+        }
+    }
+
+    /// Feed all the touch points currently on the screen, `timestamp` is the time since any fixed point in time
+    /// and must not go backwards.
+    pub fn update(&mut self, timestamp: Duration, touches: &[TouchPoint]) -> Vec<Gesture> {
+        let mut gestures = Vec::new();
+
+        match touches {
+            [] => {
+                if let State::OneFinger {
+                    start_time,
+                    start,
+                    last,
+                    long_press: false,
+                } = self.state
+                {
+                    self.finish_one_finger(timestamp, start_time, start, last, &mut gestures);
+                }
+                self.state = State::Idle;
+            }
+            [tp] => {
+                let pos = position(tp);
+                match self.state {
+                    State::Idle => {
+                        self.state = State::OneFinger {
+                            start_time: timestamp,
+                            start: pos,
+                            last: pos,
+                            long_press: false,
+                        };
+                    }
+                    State::OneFinger {
+                        start_time,
+                        start,
+                        ref mut last,
+                        ref mut long_press,
+                    } => {
+                        *last = pos;
+                        let elapsed = timestamp.saturating_sub(start_time);
+                        if !*long_press
+                            && elapsed >= self.config.long_press_duration
+                            && distance(start, pos) <= self.config.tap_max_movement as f32
+                        {
+                            *long_press = true;
+                            gestures.push(Gesture::LongPress { x: tp.x, y: tp.y });
+                        }
+                    }
+                    State::TwoFingers { .. } => self.state = State::WaitRelease,
+                    State::WaitRelease => {}
+                }
+            }
+            [tp1, tp2, ..] => {
+                let (p1, p2) = (position(tp1), position(tp2));
+                let dist = distance(p1, p2);
+                let angle = (p2.1 - p1.1).atan2(p2.0 - p1.0);
+                let center_x = ((p1.0 + p2.0) / 2.0) as u16;
+                let center_y = ((p1.1 + p2.1) / 2.0) as u16;
+
+                match self.state {
+                    State::TwoFingers {
+                        start_distance,
+                        start_angle,
+                        ref mut last_scale,
+                        ref mut last_angle,
+                    } => {
+                        if start_distance > 0.0 {
+                            let scale = dist / start_distance;
+                            if (scale - *last_scale).abs() >= self.config.pinch_min_scale_change {
+                                *last_scale = scale;
+                                gestures.push(Gesture::Pinch {
+                                    scale,
+                                    center_x,
+                                    center_y,
+                                });
+                            }
+                        }
+
+                        let rotation = normalize_angle(angle - start_angle);
+                        if normalize_angle(rotation - *last_angle).abs()
+                            >= self.config.rotate_min_angle
+                        {
+                            *last_angle = rotation;
+                            gestures.push(Gesture::Rotate {
+                                angle: rotation,
+                                center_x,
+                                center_y,
+                            });
+                        }
+                    }
+                    _ => {
+                        self.last_tap = None;
+                        self.state = State::TwoFingers {
+                            start_distance: dist,
+                            start_angle: angle,
+                            last_scale: 1.0,
+                            last_angle: 0.0,
+                        };
+                    }
+                }
+            }
+        }
+
+        gestures
+    }
+
+    // A single finger was lifted, check for a swipe or a (double) tap
+    fn finish_one_finger(
+        &mut self,
+        timestamp: Duration,
+        start_time: Duration,
+        start: (f32, f32),
+        last: (f32, f32),
+        gestures: &mut Vec<Gesture>,
+    ) {
+        let elapsed = timestamp.saturating_sub(start_time);
+        let (dx, dy) = (last.0 - start.0, last.1 - start.1);
+        let moved = distance(start, last);
+
+        if moved >= self.config.swipe_min_distance as f32
+            && elapsed <= self.config.swipe_max_duration
+        {
+            let direction = if dx.abs() >= dy.abs() {
+                if dx > 0.0 {
+                    SwipeDirection::Right
+                } else {
+                    SwipeDirection::Left
+                }
+            } else if dy > 0.0 {
+                SwipeDirection::Down
+            } else {
+                SwipeDirection::Up
+            };
+            self.last_tap = None;
+            gestures.push(Gesture::Swipe(direction));
+        } else if moved <= self.config.tap_max_movement as f32
+            && elapsed <= self.config.tap_max_duration
+        {
+            match self.last_tap {
+                Some((tap_time, tap_pos))
+                    if timestamp.saturating_sub(tap_time)
+                        <= self.config.double_tap_max_interval
+                        && distance(tap_pos, last)
+                            <= self.config.double_tap_max_distance as f32 =>
+                {
+                    self.last_tap = None;
+                    gestures.push(Gesture::DoubleTap {
+                        x: last.0 as u16,
+                        y: last.1 as u16,
+                    });
+                }
+                _ => self.last_tap = Some((timestamp, last)),
+            }
+        } else {
+            self.last_tap = None;
+        }
+    }
+}
+
+fn position(tp: &TouchPoint) -> (f32, f32) {
+    (tp.x as f32, tp.y as f32)
+}
+
+fn distance(p1: (f32, f32), p2: (f32, f32)) -> f32 {
+    let (dx, dy) = (p2.0 - p1.0, p2.1 - p1.1);
+    (dx * dx + dy * dy).sqrt()
+}
+
+// Normalize an angle to -PI..=PI
+fn normalize_angle(angle: f32) -> f32 {
+    let mut angle = angle % (2.0 * PI);
+    if angle > PI {
+        angle -= 2.0 * PI;
+    } else if angle < -PI {
+        angle += 2.0 * PI;
+    }
+    angle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::touch_record::TouchReplay;
+
+    // How often Lvgl reads the touchscreen
+    const SAMPLE_PERIOD: Duration = Duration::from_millis(10);
+
+    // Replay a single finger recording from the touch-traces folder, sampled like Lvgl reads the touchscreen
+    fn replay(recording: &str) -> Vec<Gesture> {
+        let mut replay = TouchReplay::from_reader(recording.as_bytes()).unwrap();
+        let mut recognizer = GestureRecognizer::new(GestureConfig::new());
+        let mut gestures = Vec::new();
+
+        let mut timestamp = Duration::ZERO;
+        while !replay.is_finished() {
+            gestures.extend(recognizer.update_state(timestamp, replay.state_at(timestamp)));
+            timestamp += SAMPLE_PERIOD;
+        }
+
+        gestures
+    }
+
+    // Replay a two finger trace, one line per sample with the timestamp followed by the x and y of each finger
+    // (the touch recordings only hold a single finger)
+    fn replay_fingers(trace: &str) -> Vec<Gesture> {
+        let mut recognizer = GestureRecognizer::new(GestureConfig::new());
+        let mut gestures = Vec::new();
+
+        for line in trace.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let values: Vec<u16> = line.split(',').map(|v| v.parse().unwrap()).collect();
+            let touches: Vec<TouchPoint> = values[1..]
+                .chunks_exact(2)
+                .enumerate()
+                .map(|(track_id, xy)| TouchPoint {
+                    track_id: track_id as u8,
+                    x: xy[0],
+                    y: xy[1],
+                    size: 20,
+                })
+                .collect();
+            let timestamp = Duration::from_millis(values[0] as u64);
+            gestures.extend(recognizer.update(timestamp, &touches));
+        }
+
+        gestures
+    }
+
+    #[test]
+    fn swipe() {
+        assert_eq!(
+            replay(include_str!("../touch-traces/swipe-left.csv")),
+            [Gesture::Swipe(SwipeDirection::Left)]
+        );
+    }
+
+    #[test]
+    fn long_press() {
+        assert_eq!(
+            replay(include_str!("../touch-traces/long-press.csv")),
+            [Gesture::LongPress { x: 402, y: 241 }]
+        );
+    }
+
+    #[test]
+    fn double_tap() {
+        assert_eq!(
+            replay(include_str!("../touch-traces/double-tap.csv")),
+            [Gesture::DoubleTap { x: 405, y: 242 }]
+        );
+    }
+
+    #[test]
+    fn slow_taps_are_not_a_double_tap() {
+        assert_eq!(
+            replay(include_str!("../touch-traces/click-button-twice.csv")),
+            []
+        );
+    }
+
+    #[test]
+    fn pinch() {
+        let gestures = replay_fingers(
+            "
+            # Two fingers moving apart from 200 to 400 pixels
+            1000,300,240,500,240
+            1050,280,240,520,240
+            1100,250,240,550,240
+            1150,200,240,600,240
+            1200
+            ",
+        );
+
+        assert_eq!(gestures.len(), 3);
+        assert!(gestures.iter().all(|gesture| matches!(
+            gesture,
+            Gesture::Pinch {
+                center_x: 400,
+                center_y: 240,
+                ..
+            }
+        )));
+        assert_eq!(
+            gestures.last(),
+            Some(&Gesture::Pinch {
+                scale: 2.0,
+                center_x: 400,
+                center_y: 240
+            })
+        );
+    }
+
+    #[test]
+    fn rotate() {
+        let gestures = replay_fingers(
+            "
+            # Two fingers 200 pixels apart turning clockwise by 0.2 and then 0.4 radians
+            1000,300,240,500,240
+            1050,302,220,498,260
+            1100,308,201,492,279
+            1150
+            ",
+        );
+
+        assert_eq!(gestures.len(), 2);
+        match gestures[1] {
+            Gesture::Rotate {
+                angle,
+                center_x,
+                center_y,
+            } => {
+                assert!((angle - 0.4).abs() < 0.01, "{}", angle);
+                assert_eq!((center_x, center_y), (400, 240));
+            }
+            gesture => panic!("expected a rotate, got {:?}", gesture),
+        }
+    }
+}
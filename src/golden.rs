@@ -0,0 +1,174 @@
+//! Golden image comparison for the simulator.
+//!
+//! A rendered framebuffer is compared pixel by pixel against a reference (golden) PPM image. A small tolerance
+//! is allowed so changes in anti-aliasing do not fail the comparison, but layout, style and font changes do.
+use core::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::framebuffer::{rgb565_to_rgb888, Framebuffer};
+
+/// An 8 bits per channel RGB image
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[u8; 3]>,
+}
+
+impl Image {
+    pub fn from_framebuffer(framebuffer: &Framebuffer) -> Self {
+        Self {
+            width: framebuffer.width(),
+            height: framebuffer.height(),
+            pixels: framebuffer
+                .pixels()
+                .iter()
+                .map(|p| rgb565_to_rgb888(*p))
+                .collect(),
+        }
+    }
+
+    /// Reads a binary (P6) PPM image with a max value of 255
+    pub fn read_ppm<R: BufRead>(mut reader: R) -> io::Result<Self> {
+        let magic = read_ppm_token(&mut reader)?;
+        if magic != "P6" {
+            return Err(invalid_data("not a binary PPM image"));
+        }
+
+        let width: u32 = parse_ppm_token(&mut reader)?;
+        let height: u32 = parse_ppm_token(&mut reader)?;
+        let max_value: u32 = parse_ppm_token(&mut reader)?;
+        if max_value != 255 {
+            return Err(invalid_data(
+                "only PPM images with a max value of 255 are supported",
+            ));
+        }
+
+        let mut data = vec![0u8; (width * height * 3) as usize];
+        reader.read_exact(&mut data)?;
+
+        Ok(Self {
+            width,
+            height,
+            pixels: data.chunks_exact(3).map(|p| [p[0], p[1], p[2]]).collect(),
+        })
+    }
+
+    /// Writes the image as a binary (P6) PPM image
+    pub fn write_ppm<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+        for pixel in self.pixels.iter() {
+            writer.write_all(pixel)?;
+        }
+        writer.flush()
+    }
+}
+
+// Read a whitespace separated PPM header token, the single whitespace after the last token is consumed
+fn read_ppm_token<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut token = String::new();
+    let mut byte = [0u8; 1];
+
+    loop {
+        reader.read_exact(&mut byte)?;
+        match byte[0] {
+            b'#' if token.is_empty() => {
+                // Skip comment to the end of the line
+                let mut comment = Vec::new();
+                reader.read_until(b'\n', &mut comment)?;
+            }
+            b if b.is_ascii_whitespace() => {
+                if !token.is_empty() {
+                    return Ok(token);
+                }
+            }
+            b => token.push(b as char),
+        }
+    }
+}
+
+fn parse_ppm_token<R: BufRead, T: core::str::FromStr>(reader: &mut R) -> io::Result<T> {
+    read_ppm_token(reader)?
+        .parse()
+        .map_err(|_| invalid_data("invalid PPM header"))
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// How different a rendered image may be from the golden image
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Tolerance {
+    /// Maximum difference of any color channel for a pixel to still match
+    pub channel: u8,
+    /// Maximum number of pixels that may not match
+    pub max_pixels: usize,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            channel: 8,
+            max_pixels: 0,
+        }
+    }
+}
+
+/// Why a rendered image did not match the golden image
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Mismatch {
+    Size {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    Pixels {
+        count: usize,
+        first: (u32, u32),
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::Size { expected, actual } => write!(
+                f,
+                "image size is {}x{}, expected {}x{}",
+                actual.0, actual.1, expected.0, expected.1
+            ),
+            Mismatch::Pixels { count, first } => write!(
+                f,
+                "{} pixels differ, first at ({}, {})",
+                count, first.0, first.1
+            ),
+        }
+    }
+}
+
+/// Compares the rendered image against the golden image
+pub fn compare(actual: &Image, expected: &Image, tolerance: Tolerance) -> Result<(), Mismatch> {
+    if actual.width != expected.width || actual.height != expected.height {
+        return Err(Mismatch::Size {
+            expected: (expected.width, expected.height),
+            actual: (actual.width, actual.height),
+        });
+    }
+
+    let mut count = 0;
+    let mut first = None;
+    for (index, (a, e)) in actual.pixels.iter().zip(expected.pixels.iter()).enumerate() {
+        let matches = a
+            .iter()
+            .zip(e.iter())
+            .all(|(a, e)| a.abs_diff(*e) <= tolerance.channel);
+        if !matches {
+            count += 1;
+            first.get_or_insert((index as u32 % actual.width, index as u32 / actual.width));
+        }
+    }
+
+    match first {
+        Some(first) if count > tolerance.max_pixels => Err(Mismatch::Pixels { count, first }),
+        _ => Ok(()),
+    }
+}
@@ -1,29 +1,84 @@
-/// A minimal implementation of the GT911 to work with Lvgl since Lvgl only uses a single touch point
+/// A minimal implementation of the GT911 to work with Lvgl. Lvgl only uses a single touch point but all
+/// five touch points reported by the GT911 can be read with `read_touches`.
 /// The default orientation and size are based on the aliexpress ESP 7 inch capactive touch development
 /// board model ESP-8048S070C
+use core::fmt;
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
 use embedded_hal::i2c::{I2c, SevenBitAddress};
 
-const DEFAULT_GT911_ADDRESS: u8 = 0x5d;
+use crate::calibration::AffineTransform;
+
+/// I2C address selected when INT is low during reset
+pub const DEFAULT_GT911_ADDRESS: u8 = 0x5d;
+
+/// I2C address selected when INT is high during reset
+pub const ALTERNATE_GT911_ADDRESS: u8 = 0x14;
+
+/// The maximum number of touch points the GT911 can report
+pub const MAX_TOUCH_POINTS: usize = 5;
+
+/// Size in bytes of each touch point record (track id, x, y, size, reserved)
+const POINT_RECORD_SIZE: usize = 8;
+
+/// Size in bytes of the configuration block 0x8047 - 0x80FE, not including the checksum and fresh flag
+pub const CONFIG_LEN: usize = 184;
+
+/// The largest register write - the config block followed by the checksum and the fresh flag
+const MAX_WRITE_LEN: usize = CONFIG_LEN + 2;
+
+// Offsets of the configuration fields from the start of the config block (0x8047)
+const CFG_X_OUTPUT_MAX: usize = 0x01;
+const CFG_Y_OUTPUT_MAX: usize = 0x03;
+const CFG_TOUCH_NUMBER: usize = 0x05;
+const CFG_MODULE_SWITCH1: usize = 0x06;
+const CFG_SCREEN_TOUCH_LEVEL: usize = 0x0C;
+const CFG_SCREEN_LEAVE_LEVEL: usize = 0x0D;
+const CFG_REFRESH_RATE: usize = 0x0F;
+
+// Module_Switch1 bits
+const SWITCH1_Y2Y: u8 = 0x80;
+const SWITCH1_X2X: u8 = 0x40;
+const SWITCH1_X2Y: u8 = 0x08;
 
 /// Documented registers of the device
 #[allow(dead_code)]
 #[repr(u16)]
 #[derive(Debug, Clone, Copy)]
 enum Reg {
+    ConfigVersion = 0x8047,
+    ConfigChecksum = 0x80FF,
+    ConfigFresh = 0x8100,
     ProductId = 0x8140,
     PointInfo = 0x814E,
     Point1 = 0x814F,
 }
 
 /// Represents the orientation of the device
-#[derive(Copy, Clone, Debug)]
+///
+/// The GT911 reports coordinates in the native (landscape) orientation of the panel, the orientation rotates
+/// those coordinates to match how the panel is mounted.
+///
+/// - Landscape - native orientation, no rotation
+/// - Portrait - rotated 90 degrees counter-clockwise, width and height are swapped
+/// - InvertedLandscape - rotated 180 degrees
+/// - InvertedPortrait - rotated 90 degrees clockwise, width and height are swapped
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Orientation {
-    Portrait, // Do Not use
+    Portrait,
     Landscape,
-    InvertedPortrait, // Do Not use
+    InvertedPortrait,
     InvertedLandscape,
 }
 
+impl Orientation {
+    /// Returns true if the orientation swaps the width and height of the panel
+    pub fn is_portrait(&self) -> bool {
+        matches!(self, Orientation::Portrait | Orientation::InvertedPortrait)
+    }
+}
+
 /// Represents the dimensions of the device
 #[derive(Copy, Clone, Debug)]
 pub struct Dimension {
@@ -32,16 +87,268 @@ pub struct Dimension {
 }
 
 /// Current state of the driver
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum TouchState {
     PRESSED(TouchPoint),
     RELEASED(TouchPoint),
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+/// A single touch point as reported by the GT911
+///
+/// - `track_id` stays the same for a finger as long as it remains on the screen
+/// - `size` is the touch area reported by the GT911
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub struct TouchPoint {
+    pub track_id: u8,
     pub x: u16,
     pub y: u16,
+    pub size: u16,
+}
+
+/// Fixed capacity collection of the touch points read from the GT911
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct TouchPoints {
+    points: [TouchPoint; MAX_TOUCH_POINTS],
+    len: usize,
+}
+
+impl TouchPoints {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_slice(&self) -> &[TouchPoint] {
+        &self.points[..self.len]
+    }
+
+    pub fn iter(&self) -> core::slice::Iter<'_, TouchPoint> {
+        self.as_slice().iter()
+    }
+
+    pub fn first(&self) -> Option<&TouchPoint> {
+        self.as_slice().first()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&TouchPoint> {
+        self.as_slice().get(index)
+    }
+
+    fn push(&mut self, tp: TouchPoint) {
+        if self.len < MAX_TOUCH_POINTS {
+            self.points[self.len] = tp;
+            self.len += 1;
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a TouchPoints {
+    type Item = &'a TouchPoint;
+    type IntoIter = core::slice::Iter<'a, TouchPoint>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Errors returned by the GT911 driver
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Gt911Error<E> {
+    /// Error from the underlying I2C bus
+    Bus(E),
+    /// The product id read from the GT911 is not valid ascii/utf8
+    InvalidProductId,
+    /// A touch point was outside of the panel dimensions
+    CoordinateOutOfRange { x: u16, y: u16 },
+    /// The GT911 reported more touch points than it supports
+    TooManyPoints(u8),
+    /// The GT911 buffer status bit is not set ie no new touch data is ready
+    NotReady,
+    /// Failed to drive the RST or INT pin during reset
+    Pin,
+}
+
+impl<E> From<E> for Gt911Error<E> {
+    fn from(error: E) -> Self {
+        Gt911Error::Bus(error)
+    }
+}
+
+impl<E: fmt::Debug> fmt::Display for Gt911Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Gt911Error::Bus(e) => write!(f, "GT911 I2C bus error: {:?}", e),
+            Gt911Error::InvalidProductId => write!(f, "GT911 product id is not valid"),
+            Gt911Error::CoordinateOutOfRange { x, y } => {
+                write!(f, "GT911 touch point ({}, {}) is out of range", x, y)
+            }
+            Gt911Error::TooManyPoints(count) => {
+                write!(
+                    f,
+                    "GT911 reported {} touch points, max is {}",
+                    count, MAX_TOUCH_POINTS
+                )
+            }
+            Gt911Error::NotReady => write!(f, "GT911 touch data is not ready"),
+            Gt911Error::Pin => write!(f, "GT911 failed to drive RST or INT pin"),
+        }
+    }
+}
+
+impl<E: fmt::Debug> std::error::Error for Gt911Error<E> {}
+
+/// The GT911 configuration block (registers 0x8047 - 0x80FE).
+///
+/// Read it with `GT911::read_config`, change the fields and write it back with `GT911::write_config`, the
+/// checksum is recomputed when the config is written. The GT911 only accepts a new config when the config
+/// version is greater than or equal to the version it already has.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Gt911Config {
+    data: [u8; CONFIG_LEN],
+    checksum: u8,
+}
+
+impl Gt911Config {
+    /// Create a config from the raw config block and the checksum read from 0x80FF
+    pub fn from_bytes(data: [u8; CONFIG_LEN], checksum: u8) -> Self {
+        Self { data, checksum }
+    }
+
+    /// The raw config block
+    pub fn as_bytes(&self) -> &[u8; CONFIG_LEN] {
+        &self.data
+    }
+
+    pub fn version(&self) -> u8 {
+        self.data[0]
+    }
+
+    pub fn set_version(&mut self, version: u8) {
+        self.data[0] = version;
+    }
+
+    /// X output max ie horizontal resolution
+    pub fn x_resolution(&self) -> u16 {
+        self.read_u16(CFG_X_OUTPUT_MAX)
+    }
+
+    pub fn set_x_resolution(&mut self, x_resolution: u16) {
+        self.write_u16(CFG_X_OUTPUT_MAX, x_resolution);
+    }
+
+    /// Y output max ie vertical resolution
+    pub fn y_resolution(&self) -> u16 {
+        self.read_u16(CFG_Y_OUTPUT_MAX)
+    }
+
+    pub fn set_y_resolution(&mut self, y_resolution: u16) {
+        self.write_u16(CFG_Y_OUTPUT_MAX, y_resolution);
+    }
+
+    /// Maximum number of touch points reported (1 - 5)
+    pub fn max_touch_points(&self) -> u8 {
+        self.data[CFG_TOUCH_NUMBER] & 0x0F
+    }
+
+    /// Set the maximum number of touch points reported, clamped to 1 - 5
+    pub fn set_max_touch_points(&mut self, max_touch_points: u8) {
+        let count = max_touch_points.clamp(1, MAX_TOUCH_POINTS as u8);
+        self.data[CFG_TOUCH_NUMBER] = (self.data[CFG_TOUCH_NUMBER] & 0xF0) | count;
+    }
+
+    /// Threshold for a touch to be detected
+    pub fn touch_threshold(&self) -> u8 {
+        self.data[CFG_SCREEN_TOUCH_LEVEL]
+    }
+
+    pub fn set_touch_threshold(&mut self, threshold: u8) {
+        self.data[CFG_SCREEN_TOUCH_LEVEL] = threshold;
+    }
+
+    /// Threshold for a touch to be released
+    pub fn leave_threshold(&self) -> u8 {
+        self.data[CFG_SCREEN_LEAVE_LEVEL]
+    }
+
+    pub fn set_leave_threshold(&mut self, threshold: u8) {
+        self.data[CFG_SCREEN_LEAVE_LEVEL] = threshold;
+    }
+
+    /// Coordinate report period in ms (5 - 20 ms)
+    pub fn refresh_rate_ms(&self) -> u8 {
+        5 + (self.data[CFG_REFRESH_RATE] & 0x0F)
+    }
+
+    /// Set the coordinate report period in ms, clamped to 5 - 20 ms
+    pub fn set_refresh_rate_ms(&mut self, period_ms: u8) {
+        let period = period_ms.clamp(5, 20) - 5;
+        self.data[CFG_REFRESH_RATE] = (self.data[CFG_REFRESH_RATE] & 0xF0) | period;
+    }
+
+    /// X and Y axis are swapped (X2Y)
+    pub fn swap_xy(&self) -> bool {
+        self.data[CFG_MODULE_SWITCH1] & SWITCH1_X2Y != 0
+    }
+
+    pub fn set_swap_xy(&mut self, enable: bool) {
+        self.set_switch1_bit(SWITCH1_X2Y, enable);
+    }
+
+    /// X axis is mirrored (X2X)
+    pub fn mirror_x(&self) -> bool {
+        self.data[CFG_MODULE_SWITCH1] & SWITCH1_X2X != 0
+    }
+
+    pub fn set_mirror_x(&mut self, enable: bool) {
+        self.set_switch1_bit(SWITCH1_X2X, enable);
+    }
+
+    /// Y axis is mirrored (Y2Y)
+    pub fn mirror_y(&self) -> bool {
+        self.data[CFG_MODULE_SWITCH1] & SWITCH1_Y2Y != 0
+    }
+
+    pub fn set_mirror_y(&mut self, enable: bool) {
+        self.set_switch1_bit(SWITCH1_Y2Y, enable);
+    }
+
+    /// The checksum read from the GT911 or computed by the last call to `update_checksum`
+    pub fn checksum(&self) -> u8 {
+        self.checksum
+    }
+
+    /// Checksum of the config block - two's complement of the sum of all bytes
+    pub fn compute_checksum(&self) -> u8 {
+        let sum = self.data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        (!sum).wrapping_add(1)
+    }
+
+    pub fn is_checksum_valid(&self) -> bool {
+        self.checksum == self.compute_checksum()
+    }
+
+    pub fn update_checksum(&mut self) {
+        self.checksum = self.compute_checksum();
+    }
+
+    fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.data[offset], self.data[offset + 1]])
+    }
+
+    fn write_u16(&mut self, offset: usize, value: u16) {
+        self.data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn set_switch1_bit(&mut self, bit: u8, enable: bool) {
+        if enable {
+            self.data[CFG_MODULE_SWITCH1] |= bit;
+        } else {
+            self.data[CFG_MODULE_SWITCH1] &= !bit;
+        }
+    }
 }
 
 /// Driver representation holding:
@@ -49,7 +356,9 @@ pub struct TouchPoint {
 /// - The I2C Slave address of the GT911
 /// - The I2C Bus used to communicate with the GT911
 /// - The screen/panel orientation
-/// - The screen/panel dimensions
+/// - Optional swap/mirror flags applied after the orientation
+/// - Optional touch calibration applied last
+/// - The screen/panel dimensions in the native (landscape) orientation
 pub struct GT911<I2C>
 where
     I2C: I2c<SevenBitAddress>,
@@ -57,8 +366,14 @@ where
     address: u8,
     i2c: I2C,
     orientation: Orientation,
+    swap_xy: bool,
+    mirror_x: bool,
+    mirror_y: bool,
+    calibration: Option<AffineTransform>,
     size: Dimension,
     last_tp: TouchPoint,
+    last_state: TouchState,
+    last_touches: TouchPoints,
 }
 
 impl<I2C> GT911<I2C>
@@ -70,122 +385,586 @@ where
             address: DEFAULT_GT911_ADDRESS,
             i2c,
             orientation: Orientation::Landscape,
+            swap_xy: false,
+            mirror_x: false,
+            mirror_y: false,
+            calibration: None,
             size: Dimension {
                 height: 480,
                 width: 800,
             },
-            last_tp: TouchPoint { x: 0, y: 0 },
+            last_tp: TouchPoint::default(),
+            last_state: TouchState::RELEASED(TouchPoint::default()),
+            last_touches: TouchPoints::default(),
         }
     }
 
+    /// Resets the GT911 and selects its I2C address.
+    ///
+    /// If the INT pin is given it is driven during reset to select the current address (0x5D by default, see
+    /// `set_address`), afterwards the INT pin should be configured as an input so the GT911 can use it to signal
+    /// new touch data. Without the INT pin both addresses are probed and the one that answers `read_product_id`
+    /// is used.
+    pub fn reset<RST, INT, D>(
+        &mut self,
+        rst: &mut RST,
+        mut int: Option<&mut INT>,
+        delay: &mut D,
+    ) -> Result<(), Gt911Error<I2C::Error>>
+    where
+        RST: OutputPin,
+        INT: OutputPin,
+        D: DelayNs,
+    {
+        rst.set_low().map_err(|_| Gt911Error::Pin)?;
+        if let Some(int) = int.as_mut() {
+            int.set_low().map_err(|_| Gt911Error::Pin)?;
+        }
+        delay.delay_us(200);
+
+        // INT high selects 0x14, INT low selects 0x5D, INT must be held for at least 100us before RST goes high
+        if let Some(int) = int.as_mut() {
+            if self.address == ALTERNATE_GT911_ADDRESS {
+                int.set_high().map_err(|_| Gt911Error::Pin)?;
+            }
+            delay.delay_us(200);
+        }
+
+        rst.set_high().map_err(|_| Gt911Error::Pin)?;
+        delay.delay_ms(5);
+
+        // Hold INT low for 50ms before it is released, without INT wait the same time for the GT911 to start
+        if let Some(int) = int.as_mut() {
+            int.set_low().map_err(|_| Gt911Error::Pin)?;
+        }
+        delay.delay_ms(50);
+
+        if int.is_none() {
+            self.probe_address()?;
+        }
+
+        Ok(())
+    }
+
+    /// Set the I2C address of the GT911, either `DEFAULT_GT911_ADDRESS` or `ALTERNATE_GT911_ADDRESS`
+    pub fn set_address(&mut self, address: u8) {
+        self.address = address;
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
     pub fn set_orientation(&mut self, orientation: Orientation) {
         self.orientation = orientation;
     }
 
+    /// Swap the x and y coordinates after the orientation has been applied
+    pub fn set_swap_xy(&mut self, enable: bool) {
+        self.swap_xy = enable;
+    }
+
+    /// Mirror the x coordinate after the orientation (and swap) has been applied
+    pub fn set_mirror_x(&mut self, enable: bool) {
+        self.mirror_x = enable;
+    }
+
+    /// Mirror the y coordinate after the orientation (and swap) has been applied
+    pub fn set_mirror_y(&mut self, enable: bool) {
+        self.mirror_y = enable;
+    }
+
+    /// Set the touch calibration applied after the orientation and swap/mirror flags, None removes the
+    /// calibration so raw touch points are reported (ie while calibrating).
+    pub fn set_calibration(&mut self, calibration: Option<AffineTransform>) {
+        self.calibration = calibration;
+    }
+
+    /// Set the panel size in the native (landscape) orientation, ie 480 x 800 for the 7 inch panel regardless
+    /// of the orientation.
     pub fn set_size(&mut self, height: u16, width: u16) {
         self.size = Dimension { height, width };
     }
 
+    /// The panel size after the orientation and swap flag have been applied ie the size of the touch points
+    /// coordinate space.
+    pub fn oriented_size(&self) -> Dimension {
+        let size = if self.orientation.is_portrait() {
+            Dimension {
+                height: self.size.width,
+                width: self.size.height,
+            }
+        } else {
+            self.size
+        };
+
+        if self.swap_xy {
+            Dimension {
+                height: size.width,
+                width: size.height,
+            }
+        } else {
+            size
+        }
+    }
+
+    /// The last touch point that was reported as PRESSED
+    pub fn last_touch_point(&self) -> TouchPoint {
+        self.last_tp
+    }
+
+    /// The touch points of the last touch state returned by `read_touch` or `read_touch_if_ready`, empty when
+    /// the touch was reported as RELEASED so gestures see the same touch stream as Lvgl.
+    pub fn last_touches(&self) -> TouchPoints {
+        self.last_touches
+    }
+
     // Useful function to determine if you are communicating with GT911, The GT911 must first be reset.
     // The return string should be - 911
-    pub fn read_product_id(&mut self) -> Result<String, I2C::Error> {
+    pub fn read_product_id(&mut self) -> Result<String, Gt911Error<I2C::Error>> {
         let mut rx_buf: [u8; 4] = [0; 4];
 
-        let product_id_reg: u16 = Reg::ProductId as u16;
+        self.read_reg(Reg::ProductId, &mut rx_buf)?;
 
-        let hi_byte: u8 = (product_id_reg >> 8).try_into().unwrap();
-        let lo_byte: u8 = (product_id_reg & 0xFF).try_into().unwrap();
-        let tx_buf: [u8; 2] = [hi_byte, lo_byte];
+        let product_id = std::str::from_utf8(&rx_buf).map_err(|_| Gt911Error::InvalidProductId)?;
 
-        self.i2c.write_read(self.address, &tx_buf, &mut rx_buf)?;
+        Ok(product_id.trim_end_matches('\0').to_string())
+    }
 
-        Ok(std::str::from_utf8(&rx_buf).unwrap().to_string())
+    pub fn clear_point_info_reg(&mut self) -> Result<(), Gt911Error<I2C::Error>> {
+        self.write_reg(Reg::PointInfo, &[0u8])
     }
 
-    pub fn clear_point_info_reg(&mut self) -> Result<(), I2C::Error> {
-        let point_info_reg: u16 = Reg::PointInfo as u16;
-        let hi_byte: u8 = (point_info_reg >> 8).try_into().unwrap();
-        let lo_byte: u8 = (point_info_reg & 0xFF).try_into().unwrap();
-        let tx_buf: [u8; 3] = [hi_byte, lo_byte, 0u8];
+    /// Reads the configuration block along with its checksum
+    pub fn read_config(&mut self) -> Result<Gt911Config, Gt911Error<I2C::Error>> {
+        let mut data = [0u8; CONFIG_LEN];
+        let mut checksum = [0u8; 1];
 
-        self.i2c.write(self.address, &tx_buf)?;
+        self.read_reg(Reg::ConfigVersion, &mut data)?;
+        self.read_reg(Reg::ConfigChecksum, &mut checksum)?;
 
-        Ok(())
+        Ok(Gt911Config::from_bytes(data, checksum[0]))
     }
 
-    pub fn read_touch(&mut self) -> Result<TouchState, I2C::Error> {
-        let mut rx_buf: [u8; 1] = [0xFF];
+    /// Writes the configuration block, recomputing the checksum and setting the config fresh flag so the GT911
+    /// applies the new config.
+    pub fn write_config(&mut self, config: &mut Gt911Config) -> Result<(), Gt911Error<I2C::Error>> {
+        config.update_checksum();
 
-        let point_info_reg: u16 = Reg::PointInfo as u16;
-        let hi_byte: u8 = (point_info_reg >> 8).try_into().unwrap();
-        let lo_byte: u8 = (point_info_reg & 0xFF).try_into().unwrap();
-        let tx_buf: [u8; 2] = [hi_byte, lo_byte];
+        // The config block, checksum (0x80FF) and fresh flag (0x8100) are consecutive so write them in one go
+        let mut tx_buf = [0u8; MAX_WRITE_LEN];
+        tx_buf[..CONFIG_LEN].copy_from_slice(config.as_bytes());
+        tx_buf[CONFIG_LEN] = config.checksum();
+        tx_buf[CONFIG_LEN + 1] = 1;
 
-        // Read point info register 0x814E
-        self.i2c.write_read(self.address, &tx_buf, &mut rx_buf)?;
+        self.write_reg(Reg::ConfigVersion, &tx_buf)
+    }
+
+    /// Returns the single touch point state used by Lvgl.
+    ///
+    /// When more than one finger is on the screen the first touch point is reported as PRESSED, so a second
+    /// finger does not look like the first finger was lifted. If the GT911 has no new data the touch is
+    /// reported as RELEASED with the last touch point coordinates.
+    pub fn read_touch(&mut self) -> Result<TouchState, Gt911Error<I2C::Error>> {
+        let touches = match self.read_touches() {
+            Ok(touches) => touches,
+            Err(Gt911Error::NotReady) => TouchPoints::default(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(self.update_touch_state(&touches))
+    }
+
+    /// Returns the single touch point state used by Lvgl when the GT911 INT pin is used.
+    ///
+    /// The GT911 is only read when `data_ready` is true ie the INT pin signalled new touch data, otherwise the
+    /// last touch state is returned without any I2C bus traffic.
+    pub fn read_touch_if_ready(
+        &mut self,
+        data_ready: bool,
+    ) -> Result<TouchState, Gt911Error<I2C::Error>> {
+        if data_ready {
+            match self.read_touches() {
+                Ok(touches) => {
+                    self.update_touch_state(&touches);
+                }
+                Err(Gt911Error::NotReady) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(self.last_state)
+    }
+
+    /// Reads all touch points (up to 5) currently reported by the GT911.
+    ///
+    /// An empty collection is returned when nothing is touching the screen and `Gt911Error::NotReady` is
+    /// returned when the GT911 has no new data.
+    pub fn read_touches(&mut self) -> Result<TouchPoints, Gt911Error<I2C::Error>> {
+        let mut rx_buf: [u8; 1] = [0xFF];
 
-        // Reset point_info register after reading it
-        let tx_buf: [u8; 3] = [hi_byte, lo_byte, 0u8];
-        self.i2c.write(self.address, &tx_buf)?;
+        // Read point info register 0x814E
+        self.read_reg(Reg::PointInfo, &mut rx_buf)?;
 
         let point_info = rx_buf[0];
         let status = point_info & 0x80;
 
+        // If status == 0 (no new data) there is nothing to read
+        if status == 0 {
+            return Err(Gt911Error::NotReady);
+        }
+
         // Number of detected touch points
-        let touch_pt_count = point_info & 0x07;
-        let mut touch_state = TouchState::RELEASED(self.last_tp);
-
-        // If status == 0 (no touch)
-        if status != 0 {
-            let tp = self.read_touch_point(Reg::Point1 as u16).unwrap();
-            // If touchpoint != 1 (multiple touches) then return touchstate as RELEASED with last touchpoint coordinates,
-            // otherwise return touchstate as PRESSED along with the current touchpoint coordinates.
-            if touch_pt_count != 1 {
-                touch_state = TouchState::RELEASED(self.last_tp);
-            } else {
-                touch_state = TouchState::PRESSED(tp);
-                self.last_tp = tp;
+        let touch_pt_count = point_info & 0x0F;
+        if touch_pt_count as usize > MAX_TOUCH_POINTS {
+            self.clear_point_info_reg()?;
+            return Err(Gt911Error::TooManyPoints(touch_pt_count));
+        }
+
+        // Read all the point records in a single transfer
+        let mut rx_buf = [0u8; POINT_RECORD_SIZE * MAX_TOUCH_POINTS];
+        let rx_len = POINT_RECORD_SIZE * touch_pt_count as usize;
+        if rx_len > 0 {
+            self.read_reg(Reg::Point1, &mut rx_buf[..rx_len])?;
+        }
+
+        // Reset point_info register after reading the touch points
+        self.clear_point_info_reg()?;
+
+        let mut touches = TouchPoints::default();
+        for record in rx_buf[..rx_len].chunks_exact(POINT_RECORD_SIZE) {
+            touches.push(self.decode_touch_point(record)?);
+        }
+        Ok(touches)
+    }
+
+    // Find which address the GT911 answers on, the current address is tried first and kept if neither answers
+    fn probe_address(&mut self) -> Result<(), Gt911Error<I2C::Error>> {
+        let original = self.address;
+        let other = if original == ALTERNATE_GT911_ADDRESS {
+            DEFAULT_GT911_ADDRESS
+        } else {
+            ALTERNATE_GT911_ADDRESS
+        };
+
+        let mut result = Ok(());
+        for address in [original, other] {
+            self.address = address;
+            result = self.read_product_id().map(|_| ());
+            if result.is_ok() {
+                return Ok(());
             }
         }
 
-        Ok(touch_state)
+        self.address = original;
+        result
     }
 
-    fn read_touch_point(&mut self, point_register: u16) -> Result<TouchPoint, I2C::Error> {
-        let hi_byte: u8 = (point_register >> 8).try_into().unwrap();
-        let lo_byte: u8 = (point_register & 0xFF).try_into().unwrap();
-        let tx_buf: [u8; 2] = [hi_byte, lo_byte];
+    // Update the last touch point, touch points and state from the touch points read from the GT911
+    fn update_touch_state(&mut self, touches: &TouchPoints) -> TouchState {
+        self.last_touches = *touches;
+        self.last_state = match touches.first() {
+            Some(tp) => {
+                self.last_tp = *tp;
+                TouchState::PRESSED(*tp)
+            }
+            None => TouchState::RELEASED(self.last_tp),
+        };
 
-        let mut rx_buf: [u8; 7] = [0; 7];
-        self.i2c.write_read(self.address, &tx_buf, &mut rx_buf)?;
+        self.last_state
+    }
 
-        let mut x: u16 = rx_buf[1] as u16 + ((rx_buf[2] as u16) << 8);
-        let mut y: u16 = rx_buf[3] as u16 + ((rx_buf[4] as u16) << 8);
+    // Decode a point record - track id, x (lo, hi), y (lo, hi), size (lo, hi), reserved
+    fn decode_touch_point(&self, record: &[u8]) -> Result<TouchPoint, Gt911Error<I2C::Error>> {
+        let track_id = record[0];
+        let x: u16 = u16::from_le_bytes([record[1], record[2]]);
+        let y: u16 = u16::from_le_bytes([record[3], record[4]]);
+        let size: u16 = u16::from_le_bytes([record[5], record[6]]);
 
         //println!("========== x = {:?}    y = {:?} ==========", x, y);
 
-        match self.orientation {
-            Orientation::Landscape => {
-                //x = x;
-                //y = y;
-                // x = x, y = y
+        if x >= self.size.width || y >= self.size.height {
+            return Err(Gt911Error::CoordinateOutOfRange { x, y });
+        }
+
+        let (x, y) = self.transform(x, y);
+
+        Ok(TouchPoint {
+            track_id,
+            x,
+            y,
+            size,
+        })
+    }
+
+    // Apply the orientation, the swap and mirror flags and then the calibration to a raw GT911 coordinate. The raw coordinate is
+    // clamped to the panel size so a coordinate equal to the width or height can not underflow.
+    fn transform(&self, x: u16, y: u16) -> (u16, u16) {
+        let max_x = self.size.width.saturating_sub(1);
+        let max_y = self.size.height.saturating_sub(1);
+        let x = x.min(max_x);
+        let y = y.min(max_y);
+
+        let (mut x, mut y) = match self.orientation {
+            Orientation::Landscape => (x, y),
+            Orientation::Portrait => (y, max_x - x),
+            Orientation::InvertedLandscape => (max_x - x, max_y - y),
+            Orientation::InvertedPortrait => (max_y - y, x),
+        };
+
+        if self.swap_xy {
+            core::mem::swap(&mut x, &mut y);
+        }
+
+        let size = self.oriented_size();
+        if self.mirror_x {
+            x = size.width.saturating_sub(1).saturating_sub(x);
+        }
+        if self.mirror_y {
+            y = size.height.saturating_sub(1).saturating_sub(y);
+        }
+
+        if let Some(calibration) = self.calibration {
+            let (cx, cy) = calibration.apply(x as f32, y as f32);
+            // Float to int casts saturate so negative values become 0
+            x = (cx.round() as u16).min(size.width.saturating_sub(1));
+            y = (cy.round() as u16).min(size.height.saturating_sub(1));
+        }
+
+        (x, y)
+    }
+
+    // Read consecutive registers starting at `reg` into `rx_buf`
+    fn read_reg(&mut self, reg: Reg, rx_buf: &mut [u8]) -> Result<(), Gt911Error<I2C::Error>> {
+        let tx_buf: [u8; 2] = (reg as u16).to_be_bytes();
+
+        self.i2c.write_read(self.address, &tx_buf, rx_buf)?;
+
+        Ok(())
+    }
+
+    // Write `data` to consecutive registers starting at `reg`
+    fn write_reg(&mut self, reg: Reg, data: &[u8]) -> Result<(), Gt911Error<I2C::Error>> {
+        let [hi_byte, lo_byte] = (reg as u16).to_be_bytes();
+
+        let len = data.len().min(MAX_WRITE_LEN);
+        let mut tx_buf = [0u8; 2 + MAX_WRITE_LEN];
+        tx_buf[0] = hi_byte;
+        tx_buf[1] = lo_byte;
+        tx_buf[2..2 + len].copy_from_slice(&data[..len]);
+
+        self.i2c.write(self.address, &tx_buf[..2 + len])?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal::i2c::{ErrorKind, ErrorType, NoAcknowledgeSource, Operation};
+
+    // First register of the mock register file
+    const REG_BASE: usize = 0x8000;
+
+    // A GT911 register file answering on a single I2C address
+    struct MockI2c {
+        address: u8,
+        regs: Vec<u8>,
+        pointer: usize,
+    }
+
+    impl MockI2c {
+        fn new(address: u8) -> Self {
+            let mut regs = vec![0; 0x200];
+            regs[Reg::ProductId as usize - REG_BASE..][..4].copy_from_slice(b"911\0");
+            Self {
+                address,
+                regs,
+                pointer: 0,
             }
-            Orientation::Portrait => {
-                let temp: u16 = x;
-                x = y;
-                y = self.size.height - temp;
+        }
+
+        // Report raw touch points with the buffer status bit set
+        fn touch(&mut self, points: &[(u16, u16)]) {
+            self.regs[Reg::PointInfo as usize - REG_BASE] = 0x80 | points.len() as u8;
+            for (i, (x, y)) in points.iter().enumerate() {
+                let record = &mut self.regs
+                    [Reg::Point1 as usize - REG_BASE + i * POINT_RECORD_SIZE..]
+                    [..POINT_RECORD_SIZE];
+                record[0] = i as u8;
+                record[1..3].copy_from_slice(&x.to_le_bytes());
+                record[3..5].copy_from_slice(&y.to_le_bytes());
+                record[5..7].copy_from_slice(&20u16.to_le_bytes());
             }
-            Orientation::InvertedLandscape => {
-                x = self.size.width - x;
-                y = self.size.height - y;
+        }
+    }
+
+    impl ErrorType for MockI2c {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for MockI2c {
+        fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            if address != self.address {
+                return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
             }
-            Orientation::InvertedPortrait => {
-                let temp: u16 = x;
-                x = self.size.width - y;
-                y = temp;
+
+            for operation in operations {
+                match operation {
+                    Operation::Write(data) => {
+                        let reg = u16::from_be_bytes([data[0], data[1]]) as usize - REG_BASE;
+                        self.regs[reg..reg + data.len() - 2].copy_from_slice(&data[2..]);
+                        self.pointer = reg;
+                    }
+                    Operation::Read(buf) => {
+                        let len = buf.len();
+                        buf.copy_from_slice(&self.regs[self.pointer..self.pointer + len]);
+                    }
+                }
             }
+
+            Ok(())
         }
+    }
+
+    // An 800 x 480 panel, the GT911 default size
+    fn new_gt911() -> GT911<MockI2c> {
+        GT911::new(MockI2c::new(DEFAULT_GT911_ADDRESS))
+    }
+
+    fn read_point(gt911: &mut GT911<MockI2c>, x: u16, y: u16) -> (u16, u16) {
+        gt911.i2c.touch(&[(x, y)]);
+        let touches = gt911.read_touches().unwrap();
+        let tp = touches.first().unwrap();
+        (tp.x, tp.y)
+    }
+
+    #[test]
+    fn orientations() {
+        let mut gt911 = new_gt911();
+        assert_eq!(read_point(&mut gt911, 100, 50), (100, 50));
+
+        gt911.set_orientation(Orientation::Portrait);
+        assert_eq!(read_point(&mut gt911, 100, 50), (50, 699));
+
+        gt911.set_orientation(Orientation::InvertedLandscape);
+        assert_eq!(read_point(&mut gt911, 100, 50), (699, 429));
+
+        gt911.set_orientation(Orientation::InvertedPortrait);
+        assert_eq!(read_point(&mut gt911, 100, 50), (429, 100));
+    }
+
+    #[test]
+    fn portrait_corners_stay_in_range() {
+        let mut gt911 = new_gt911();
+        gt911.set_orientation(Orientation::Portrait);
+        assert_eq!(read_point(&mut gt911, 0, 0), (0, 799));
+        assert_eq!(read_point(&mut gt911, 799, 479), (479, 0));
+
+        gt911.set_orientation(Orientation::InvertedPortrait);
+        assert_eq!(read_point(&mut gt911, 0, 0), (479, 0));
+        assert_eq!(read_point(&mut gt911, 799, 479), (0, 799));
+    }
+
+    #[test]
+    fn coordinate_equal_to_size() {
+        let mut gt911 = new_gt911();
+        gt911.i2c.touch(&[(800, 100)]);
+        assert_eq!(
+            gt911.read_touches(),
+            Err(Gt911Error::CoordinateOutOfRange { x: 800, y: 100 })
+        );
+        gt911.i2c.touch(&[(100, 480)]);
+        assert_eq!(
+            gt911.read_touches(),
+            Err(Gt911Error::CoordinateOutOfRange { x: 100, y: 480 })
+        );
+
+        // The transform clamps the coordinate instead of underflowing
+        gt911.set_orientation(Orientation::InvertedLandscape);
+        assert_eq!(gt911.transform(800, 480), (0, 0));
+    }
+
+    #[test]
+    fn swap_and_mirror_flags() {
+        let mut gt911 = new_gt911();
+        gt911.set_swap_xy(true);
+        assert_eq!(read_point(&mut gt911, 100, 50), (50, 100));
+        assert_eq!(gt911.oriented_size().width, 480);
+
+        let mut gt911 = new_gt911();
+        gt911.set_mirror_x(true);
+        assert_eq!(read_point(&mut gt911, 100, 50), (699, 50));
+
+        let mut gt911 = new_gt911();
+        gt911.set_mirror_y(true);
+        assert_eq!(read_point(&mut gt911, 100, 50), (100, 429));
+
+        // The flags are applied after the orientation in the oriented coordinate space
+        let mut gt911 = new_gt911();
+        gt911.set_orientation(Orientation::Portrait);
+        gt911.set_mirror_x(true);
+        assert_eq!(read_point(&mut gt911, 100, 50), (429, 699));
+    }
+
+    #[test]
+    fn last_touches_follow_touch_state() {
+        let mut gt911 = new_gt911();
+        gt911.i2c.touch(&[(10, 20), (30, 40)]);
+        assert!(matches!(gt911.read_touch(), Ok(TouchState::PRESSED(_))));
+        assert_eq!(gt911.last_touches().len(), 2);
+
+        // No new data while polling is a release
+        assert!(matches!(gt911.read_touch(), Ok(TouchState::RELEASED(_))));
+        assert!(gt911.last_touches().is_empty());
+
+        // No new data with the INT pin keeps the last state
+        gt911.i2c.touch(&[(10, 20)]);
+        assert!(matches!(
+            gt911.read_touch_if_ready(true),
+            Ok(TouchState::PRESSED(_))
+        ));
+        assert!(matches!(
+            gt911.read_touch_if_ready(false),
+            Ok(TouchState::PRESSED(_))
+        ));
+        assert_eq!(gt911.last_touches().len(), 1);
+
+        gt911.i2c.touch(&[]);
+        assert!(matches!(
+            gt911.read_touch_if_ready(true),
+            Ok(TouchState::RELEASED(_))
+        ));
+        assert!(gt911.last_touches().is_empty());
+    }
+
+    #[test]
+    fn probe_address() {
+        let mut gt911 = GT911::new(MockI2c::new(ALTERNATE_GT911_ADDRESS));
+        assert!(gt911.probe_address().is_ok());
+        assert_eq!(gt911.address(), ALTERNATE_GT911_ADDRESS);
+
+        // The address is left unchanged when the GT911 does not answer on either address
+        let mut gt911 = GT911::new(MockI2c::new(0x10));
+        assert!(matches!(gt911.probe_address(), Err(Gt911Error::Bus(_))));
+        assert_eq!(gt911.address(), DEFAULT_GT911_ADDRESS);
+    }
 
-        Ok(TouchPoint { x, y })
+    #[test]
+    fn reads_all_touch_points() {
+        let mut gt911 = new_gt911();
+        gt911.i2c.touch(&[(10, 20), (30, 40)]);
+        let touches = gt911.read_touches().unwrap();
+        assert_eq!(touches.len(), 2);
+        assert_eq!((touches.as_slice()[1].x, touches.as_slice()[1].y), (30, 40));
+        assert_eq!(touches.as_slice()[1].track_id, 1);
+
+        // The point info register is cleared after reading
+        assert_eq!(gt911.read_touches(), Err(Gt911Error::NotReady));
     }
 }
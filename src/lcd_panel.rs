@@ -3,354 +3,67 @@
 //! The default configuration and timings are based upon the Aliexpress Esp32S-8048S070C development board.
 use std::ptr::null_mut;
 
-use core::cell::UnsafeCell;
+use core::ffi::c_void;
+use core::mem::{align_of, size_of};
+use core::ops::{Deref, DerefMut};
 
-use esp_idf_svc::sys::{
-    esp,
-    esp_lcd_new_rgb_panel,
-    esp_lcd_panel_del,
-    esp_lcd_panel_draw_bitmap,
-    esp_lcd_panel_handle_t,
-    esp_lcd_panel_init,
-    esp_lcd_panel_reset,
-    esp_lcd_rgb_panel_config_t,
-    esp_lcd_rgb_panel_config_t__bindgen_ty_1,
-    //esp_lcd_rgb_panel_get_frame_buffer,
-    esp_lcd_rgb_timing_t,
-    esp_lcd_rgb_timing_t__bindgen_ty_1,
-    soc_periph_lcd_clk_src_t,
-    soc_periph_lcd_clk_src_t_LCD_CLK_SRC_PLL160M,
-    EspError,
-};
-
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub struct PanelConfig {
-    pub clk_src: soc_periph_lcd_clk_src_t,
-    pub data_width: usize,
-    pub bits_per_pixel: usize,
-    pub num_fbs: usize,
-    pub bounce_buffer_size_px: usize,
-    pub sram_trans_align: usize,
-    pub psram_trans_align: usize,
-    pub hsync_gpio_num: i32,
-    pub vsync_gpio_num: i32,
-    pub de_gpio_num: i32,
-    pub pclk_gpio_num: i32,
-    pub disp_gpio_num: i32,
-    pub data_gpio_nums: [i32; 16],
-}
-
-impl PanelConfig {
-    pub fn new() -> Self {
-        Self::default()
-    }
-
-    #[must_use]
-    pub fn clk_src(mut self, source: soc_periph_lcd_clk_src_t) -> Self {
-        self.clk_src = source;
-        self
-    }
-
-    #[must_use]
-    pub fn data_width(mut self, data_width: usize) -> Self {
-        self.data_width = data_width;
-        self
-    }
-
-    #[must_use]
-    pub fn bits_per_pixel(mut self, bits_per_pixel: usize) -> Self {
-        self.bits_per_pixel = bits_per_pixel;
-        self
-    }
-
-    #[must_use]
-    pub fn num_fbs(mut self, num_fbs: usize) -> Self {
-        self.num_fbs = num_fbs;
-        self
-    }
-
-    #[must_use]
-    pub fn bounce_buffer_size_px(mut self, bounce_buffer_size_px: usize) -> Self {
-        self.bounce_buffer_size_px = bounce_buffer_size_px;
-        self
-    }
-
-    #[must_use]
-    pub fn sram_trans_align(mut self, sram_trans_align: usize) -> Self {
-        self.sram_trans_align = sram_trans_align;
-        self
-    }
-
-    #[must_use]
-    pub fn hsync_gpio_num(mut self, hsync_gpio_num: i32) -> Self {
-        self.hsync_gpio_num = hsync_gpio_num;
-        self
-    }
-
-    #[must_use]
-    pub fn vsync_gpio_num(mut self, vsync_gpio_num: i32) -> Self {
-        self.vsync_gpio_num = vsync_gpio_num;
-        self
-    }
-
-    #[must_use]
-    pub fn de_gpio_num(mut self, de_gpio_num: i32) -> Self {
-        self.de_gpio_num = de_gpio_num;
-        self
-    }
-
-    #[must_use]
-    pub fn pclk_gpio_num(mut self, pclk_gpio_num: i32) -> Self {
-        self.pclk_gpio_num = pclk_gpio_num;
-        self
-    }
-
-    #[must_use]
-    pub fn disp_gpio_num(mut self, disp_gpio_num: i32) -> Self {
-        self.disp_gpio_num = disp_gpio_num;
-        self
-    }
-
-    #[must_use]
-    pub fn data_gpio_nums(mut self, data_gpio_nums: [i32; 16]) -> Self {
-        self.data_gpio_nums = data_gpio_nums;
-        self
-    }
-}
-
-impl Default for PanelConfig {
-    fn default() -> Self {
-        Self {
-            clk_src: soc_periph_lcd_clk_src_t_LCD_CLK_SRC_PLL160M,
-            data_width: 16,
-            bits_per_pixel: 0,
-            num_fbs: 1,
-            bounce_buffer_size_px: 0,
-            sram_trans_align: 8,
-            psram_trans_align: 64,
-            hsync_gpio_num: 39,
-            vsync_gpio_num: 40,
-            de_gpio_num: 41,
-            pclk_gpio_num: 42,
-            disp_gpio_num: -1,
-            data_gpio_nums: [15, 7, 6, 5, 4, 9, 46, 3, 8, 16, 1, 14, 21, 47, 48, 45],
-        }
-    }
-}
-
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub struct PanelFlagsConfig {
-    pub disp_active_low: u32,
-    pub refresh_on_demand: u32,
-    pub fb_in_psram: u32,
-    pub double_fb: u32,
-    pub no_fb: u32,
-    pub bb_invalidate_cache: u32,
-}
-
-impl PanelFlagsConfig {
-    pub fn new() -> Self {
-        Self::default()
-    }
-
-    #[must_use]
-    pub fn disp_active_low(mut self, enable: bool) -> Self {
-        self.disp_active_low = enable.into();
-        self
-    }
-
-    #[must_use]
-    pub fn refresh_on_demand(mut self, enable: bool) -> Self {
-        self.refresh_on_demand = enable.into();
-        self
-    }
-
-    #[must_use]
-    pub fn fb_in_psram(mut self, enable: bool) -> Self {
-        self.fb_in_psram = enable.into();
-        self
-    }
-
-    #[must_use]
-    pub fn double_fb(mut self, enable: bool) -> Self {
-        self.double_fb = enable.into();
-        self
-    }
-
-    #[must_use]
-    pub fn no_fb(mut self, enable: bool) -> Self {
-        self.no_fb = enable.into();
-        self
-    }
-
-    #[must_use]
-    pub fn bb_invalidate_cache(mut self, enable: bool) -> Self {
-        self.bb_invalidate_cache = enable.into();
-        self
-    }
-}
-
-impl Default for PanelFlagsConfig {
-    fn default() -> Self {
-        Self {
-            disp_active_low: 0,
-            refresh_on_demand: 0,
-            fb_in_psram: 1,
-            double_fb: 0,
-            no_fb: 0,
-            bb_invalidate_cache: 0,
-        }
-    }
-}
-
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub struct TimingsConfig {
-    pub pclk_hz: u32,
-    pub horz_res: u32,
-    pub vert_res: u32,
-    pub hsync_pulse_width: u32,
-    pub hsync_back_porch: u32,
-    pub hsync_front_porch: u32,
-    pub vsync_pulse_width: u32,
-    pub vsync_back_porch: u32,
-    pub vsync_front_porch: u32,
-}
-
-impl TimingsConfig {
-    pub fn new() -> Self {
-        Self::default()
-    }
+use log::*;
 
-    #[must_use]
-    pub fn pclk_hz(mut self, hz: u32) -> Self {
-        self.pclk_hz = hz;
-        self
-    }
-
-    #[must_use]
-    pub fn horz_res(mut self, hres: u32) -> Self {
-        self.horz_res = hres;
-        self
-    }
-
-    #[must_use]
-    pub fn vert_res(mut self, vres: u32) -> Self {
-        self.vert_res = vres;
-        self
-    }
-
-    #[must_use]
-    pub fn hsync_pulse_width(mut self, hsync_pulse_width: u32) -> Self {
-        self.hsync_pulse_width = hsync_pulse_width;
-        self
-    }
-
-    #[must_use]
-    pub fn hsync_back_porch(mut self, hsync_back_porch: u32) -> Self {
-        self.hsync_back_porch = hsync_back_porch;
-        self
-    }
-
-    #[must_use]
-    pub fn hsync_front_porch(mut self, hsync_front_porch: u32) -> Self {
-        self.hsync_front_porch = hsync_front_porch;
-        self
-    }
-
-    #[must_use]
-    pub fn vsync_pulse_width(mut self, vsync_pulse_width: u32) -> Self {
-        self.vsync_pulse_width = vsync_pulse_width;
-        self
-    }
-
-    #[must_use]
-    pub fn vsync_back_porch(mut self, vsync_back_porch: u32) -> Self {
-        self.vsync_back_porch = vsync_back_porch;
-        self
-    }
+use embedded_graphics_core::draw_target::DrawTarget;
+use embedded_graphics_core::geometry::{Dimensions, OriginDimensions, Size};
+use embedded_graphics_core::pixelcolor::raw::RawU16;
+use embedded_graphics_core::pixelcolor::{IntoStorage, Rgb565};
+use embedded_graphics_core::primitives::{PointsIter, Rectangle};
+use embedded_graphics_core::Pixel;
 
-    #[must_use]
-    pub fn vsync_front_porch(mut self, vsync_front_porch: u32) -> Self {
-        self.vsync_front_porch = vsync_front_porch;
-        self
-    }
-}
-
-impl Default for TimingsConfig {
-    fn default() -> Self {
-        Self {
-            pclk_hz: (16 * 1000 * 1000),
-            horz_res: 800,
-            vert_res: 480,
-            hsync_pulse_width: 30,
-            hsync_back_porch: 16,
-            hsync_front_porch: 210,
-            vsync_pulse_width: 13,
-            vsync_back_porch: 10,
-            vsync_front_porch: 22,
-        }
-    }
-}
-
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub struct TimingFlagsConfig {
-    pub hsync_idle_low: u32,
-    pub vsync_idle_low: u32,
-    pub de_idle_high: u32,
-    pub pclk_active_neg: u32,
-    pub pclk_idle_high: u32,
-}
-
-impl TimingFlagsConfig {
-    pub fn new() -> Self {
-        Self::default()
-    }
-
-    #[must_use]
-    pub fn hsync_idle_low(mut self, enable: bool) -> Self {
-        self.hsync_idle_low = enable.into();
-        self
-    }
+use esp_idf_svc::sys::{
+    esp, esp_lcd_new_rgb_panel, esp_lcd_panel_del, esp_lcd_panel_draw_bitmap,
+    esp_lcd_panel_handle_t, esp_lcd_panel_init, esp_lcd_panel_reset, esp_lcd_rgb_panel_config_t,
+    esp_lcd_rgb_panel_config_t__bindgen_ty_1, esp_lcd_rgb_panel_event_callbacks_t,
+    esp_lcd_rgb_panel_get_frame_buffer, esp_lcd_rgb_panel_refresh,
+    esp_lcd_rgb_panel_register_event_callbacks, esp_lcd_rgb_timing_t,
+    esp_lcd_rgb_timing_t__bindgen_ty_1, EspError, ESP_ERR_INVALID_SIZE,
+};
 
-    #[must_use]
-    pub fn vsync_idle_low(mut self, enable: bool) -> Self {
-        self.vsync_idle_low = enable.into();
-        self
-    }
+use crate::display_backend::{ColorFormat, DisplayBackend};
+use crate::framebuffer::rgb565_to_rgb888;
+use crate::rotation::Rotation;
 
-    #[must_use]
-    pub fn de_idle_high(mut self, enable: bool) -> Self {
-        self.de_idle_high = enable.into();
-        self
-    }
+pub use crate::panel_config::{
+    ColorMode, PanelConfig, PanelFlagsConfig, TimingFlagsConfig, TimingsConfig,
+};
 
-    #[must_use]
-    pub fn pclk_active_neg(mut self, enable: bool) -> Self {
-        self.pclk_active_neg = enable.into();
-        self
-    }
+/// Maximum number of frame buffers supported by the RGB LCD driver
+const MAX_NUM_FBS: usize = 3;
 
-    #[must_use]
-    pub fn set_pclk_idle_high(mut self, enable: bool) -> Self {
-        self.pclk_idle_high = enable.into();
-        self
-    }
-}
+/// Number of lines drawn at a time by `fill_contiguous` and `fill_solid`
+const FILL_BAND_LINES: usize = 16;
 
-impl Default for TimingFlagsConfig {
-    fn default() -> Self {
-        Self {
-            hsync_idle_low: 0,
-            vsync_idle_low: 0,
-            de_idle_high: 0,
-            pclk_active_neg: 1,
-            pclk_idle_high: 0,
-        }
-    }
-}
+// The pixels are passed to ESP-IDF and Lvgl as RGB565 u16 values, the frame buffers and Lvgl draw buffer can only
+// be used as Rgb565 and lvgl::Color slices if they have the same layout as u16
+const _: () =
+    assert!(size_of::<Rgb565>() == size_of::<u16>() && align_of::<Rgb565>() == align_of::<u16>());
+const _: () = assert!(
+    size_of::<lvgl::Color>() == size_of::<u16>() && align_of::<lvgl::Color>() == align_of::<u16>()
+);
 
 pub struct LcdPanel {
     pub panel: esp_lcd_panel_handle_t,
+    width: usize,
+    height: usize,
+    num_fbs: usize,
+    frame_buffers: [*mut c_void; MAX_NUM_FBS],
+    rotation: Rotation,
+    color_mode: ColorMode,
+    // The rotated pixels of the region being flushed
+    rotate_buffer: Vec<u16>,
+    // The pixels of the region being flushed converted to the color mode
+    convert_buffer: Vec<u8>,
+    // The pixels of the band being drawn by fill_contiguous and fill_solid
+    fill_buffer: Vec<u16>,
+    refresh_on_demand: bool,
+    // True if anything was drawn since the last refresh, only tracked with refresh_on_demand
+    dirty: bool,
 }
 
 impl LcdPanel {
@@ -372,7 +85,7 @@ impl LcdPanel {
             vsync_front_porch: timing_config.vsync_front_porch,
             flags: {
                 let mut flags = esp_lcd_rgb_timing_t__bindgen_ty_1::default();
-                flags.set_vsync_idle_low(timing_flags_config.vsync_idle_low);
+                flags.set_hsync_idle_low(timing_flags_config.hsync_idle_low);
                 flags.set_vsync_idle_low(timing_flags_config.vsync_idle_low);
                 flags.set_de_idle_high(timing_flags_config.de_idle_high);
                 flags.set_pclk_active_neg(timing_flags_config.pclk_active_neg);
@@ -381,6 +94,8 @@ impl LcdPanel {
             },
         };
 
+        let num_fbs = panel_config.frame_buffer_count(panel_flags_config);
+
         let panel_config = esp_lcd_rgb_panel_config_t {
             clk_src: panel_config.clk_src,
             timings,
@@ -402,12 +117,14 @@ impl LcdPanel {
                 flags.set_refresh_on_demand(panel_flags_config.refresh_on_demand);
                 flags.set_fb_in_psram(panel_flags_config.fb_in_psram);
                 flags.set_double_fb(panel_flags_config.double_fb);
+                flags.set_no_fb(panel_flags_config.no_fb);
                 flags.set_bb_invalidate_cache(panel_flags_config.bb_invalidate_cache);
                 flags
             },
         };
 
         let mut panel = null_mut() as esp_lcd_panel_handle_t;
+        let mut frame_buffers = [null_mut(); MAX_NUM_FBS];
 
         unsafe {
             // create panel
@@ -418,18 +135,235 @@ impl LcdPanel {
 
             // initialize panel
             esp!(esp_lcd_panel_init(panel))?;
+
+            // get the frame buffers allocated by the driver
+            if num_fbs > 0 {
+                let [fb0, fb1, fb2] = &mut frame_buffers;
+                esp!(esp_lcd_rgb_panel_get_frame_buffer(
+                    panel,
+                    num_fbs as u32,
+                    fb0 as *mut *mut c_void,
+                    fb1 as *mut *mut c_void,
+                    fb2 as *mut *mut c_void,
+                ))?;
+            }
         };
 
-        Ok(Self { panel })
+        Ok(Self {
+            panel,
+            width: timing_config.horz_res as usize,
+            height: timing_config.vert_res as usize,
+            num_fbs,
+            frame_buffers,
+            rotation: Rotation::Deg0,
+            color_mode: panel_config.color_mode,
+            rotate_buffer: Vec::new(),
+            convert_buffer: Vec::new(),
+            fill_buffer: Vec::new(),
+            refresh_on_demand: panel_flags_config.refresh_on_demand != 0,
+            dirty: false,
+        })
+    }
+
+    /// Sets the display rotation, the regions passed to `set_pixels` are then in the rotated coordinates. The
+    /// frame buffers (see `frame_buffer`) are always in the native panel orientation.
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.rotation = rotation;
+    }
+
+    pub fn rotation(&self) -> Rotation {
+        self.rotation
+    }
+
+    /// The width and height of the display after the rotation is applied
+    pub fn display_size(&self) -> (u32, u32) {
+        self.rotation
+            .display_size(self.width as u32, self.height as u32)
+    }
+
+    pub fn color_mode(&self) -> ColorMode {
+        self.color_mode
+    }
+
+    /// The number of frame buffers allocated by the driver, 0 when the no_fb flag is set
+    pub fn frame_buffer_count(&self) -> usize {
+        self.num_fbs
+    }
+
+    // The frame buffers allocated by the driver
+    pub(crate) fn frame_buffer_ptrs(&self) -> &[*mut c_void] {
+        &self.frame_buffers[..self.num_fbs]
+    }
+
+    /// Returns true if the panel is only sent a frame when it is refreshed (the refresh_on_demand flag)
+    pub fn refresh_on_demand(&self) -> bool {
+        self.refresh_on_demand
+    }
+
+    /// Sends the frame buffer to the panel, the panel must have been created with the refresh_on_demand flag.
+    ///
+    /// The RGB peripheral has no partial refresh, the panel has no frame memory to update a region of and the
+    /// DMA always sends the whole frame buffer with the HSYNC and VSYNC timings, so only drawing something decides
+    /// whether a frame is sent. Lvgl refreshes the panel after each flush
+    /// (see `DisplayBackend::flush_ready`), call this after drawing into a frame buffer (see `frame_buffer`).
+    pub fn refresh(&mut self) -> Result<(), EspError> {
+        self.dirty = false;
+        esp!(unsafe { esp_lcd_rgb_panel_refresh(self.panel) })
+    }
+
+    /// Refreshes the panel if anything was drawn since the last refresh, returns true if the panel was refreshed.
+    /// Nothing is tracked without the refresh_on_demand flag so this never refreshes a streaming panel.
+    pub fn refresh_if_dirty(&mut self) -> Result<bool, EspError> {
+        if !self.dirty {
+            return Ok(false);
+        }
+
+        self.refresh()?;
+
+        Ok(true)
+    }
+
+    /// Registers the VSYNC and bounce buffer callbacks, the callbacks are called from the LCD interrupt.
+    ///
+    /// # Safety
+    ///
+    /// `user_ctx` is passed to the callbacks and must stay valid for as long as the panel exists.
+    pub unsafe fn register_event_callbacks(
+        &mut self,
+        callbacks: &esp_lcd_rgb_panel_event_callbacks_t,
+        user_ctx: *mut c_void,
+    ) -> Result<(), EspError> {
+        esp!(esp_lcd_rgb_panel_register_event_callbacks(
+            self.panel, callbacks, user_ctx
+        ))
+    }
+
+    /// Gives direct access to a frame buffer of the panel.
+    ///
+    /// The frame buffer is `width * height` pixels, row first. The changes are written back from the cache to
+    /// the PSRAM, and with more than one frame buffer the frame buffer is shown on the panel, when the returned
+    /// `PanelFrameBuffer` is dropped. With refresh_on_demand call `refresh` once it is dropped to send it to the
+    /// panel. Returns None if the color mode is not RGB565.
+    pub fn frame_buffer(&mut self, index: usize) -> Option<PanelFrameBuffer<'_>> {
+        if index >= self.num_fbs || self.color_mode != ColorMode::Rgb565 {
+            return None;
+        }
+
+        // The frame buffer was allocated by the driver for the life of the panel and the mutable borrow of the
+        // panel stops any other access to it from Rust
+        let pixels = unsafe {
+            core::slice::from_raw_parts_mut(
+                self.frame_buffers[index] as *mut Rgb565,
+                self.width * self.height,
+            )
+        };
+
+        Some(PanelFrameBuffer {
+            panel: self.panel,
+            width: self.width,
+            height: self.height,
+            pixels,
+        })
+    }
+
+    ///
+    /// Sets pixel colors in a rectangular region.
+    ///
+    /// The RGB565 `pixels` are copied to the given region starting at the top left corner and continuing, row
+    /// first, to the bottom right corner. `pixels` must hold at least `(ex - sx) * (ey - sy)` values. The region
+    /// is in the rotated coordinates (see `set_rotation`) and the pixels are converted to the panel color mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `sx` - x coordinate start
+    /// * `sy` - y coordinate start
+    /// * `ex` - x coordinate end (exclusive)
+    /// * `ey` - y coordinate end (exclusive)
+    /// * `pixels` - the RGB565 pixel data
+    pub fn set_pixels(
+        &mut self,
+        sx: i32,
+        sy: i32,
+        ex: i32,
+        ey: i32,
+        pixels: &[u16],
+    ) -> Result<(), EspError> {
+        let (width, height) = ((ex - sx).max(0) as usize, (ey - sy).max(0) as usize);
+        let len = width * height;
+        if pixels.len() < len {
+            return Err(EspError::from_infallible::<ESP_ERR_INVALID_SIZE>());
+        }
+
+        // Transpose the region into the native panel orientation
+        let (sx, sy, ex, ey, pixels) = if self.rotation == Rotation::Deg0 {
+            (sx, sy, ex, ey, pixels)
+        } else {
+            self.rotate_buffer.resize(len, 0);
+            self.rotation
+                .rotate_pixels(pixels, width, height, &mut self.rotate_buffer);
+            let (sx, sy, ex, ey) =
+                self.rotation
+                    .panel_region(sx, sy, ex, ey, self.width as i32, self.height as i32);
+            (sx, sy, ex, ey, &self.rotate_buffer[..])
+        };
+
+        if self.refresh_on_demand && sx < ex && sy < ey {
+            self.dirty = true;
+        }
+
+        // Convert the pixels to the color mode of the frame buffer
+        let data = match self.color_mode {
+            ColorMode::Rgb565 => pixels.as_ptr() as *const c_void,
+            ColorMode::Rgb565Swapped => {
+                self.convert_buffer.clear();
+                self.convert_buffer
+                    .extend(pixels[..len].iter().flat_map(|pixel| pixel.to_be_bytes()));
+                self.convert_buffer.as_ptr() as *const c_void
+            }
+            ColorMode::Rgb888 => {
+                self.convert_buffer.clear();
+                self.convert_buffer
+                    .extend(pixels[..len].iter().flat_map(|pixel| {
+                        let [r, g, b] = rgb565_to_rgb888(Rgb565::from(RawU16::new(*pixel)));
+                        [b, g, r]
+                    }));
+                self.convert_buffer.as_ptr() as *const c_void
+            }
+        };
+
+        unsafe {
+            esp!(esp_lcd_panel_draw_bitmap(self.panel, sx, sy, ex, ey, data))?;
+        };
+
+        Ok(())
+    }
+
+    ///
+    /// Sets pixel colors in a rectangular region from the Lvgl draw buffer without copying the colors.
+    ///
+    /// See `set_pixels`, `colors` is the start of the Lvgl draw buffer.
+    pub fn set_pixels_lvgl(
+        &mut self,
+        sx: i32,
+        sy: i32,
+        ex: i32,
+        ey: i32,
+        colors: &[lvgl::Color],
+    ) -> Result<(), EspError> {
+        // lvgl::Color is the 16 bit lv_color_t which holds a RGB565 value (LV_COLOR_DEPTH 16)
+        let pixels =
+            unsafe { core::slice::from_raw_parts(colors.as_ptr() as *const u16, colors.len()) };
+
+        self.set_pixels(sx, sy, ex, ey, pixels)
     }
 
     ///
     /// Sets pixel colors in a rectangular region.
     ///
     /// The color values from the `colors` iterator will be drawn to the given region starting
-    /// at the top left corner and continuing, row first, to the bottom right corner. No bounds
-    /// checking is performed on the `colors` iterator and drawing will wrap around if the
-    /// iterator returns more color values than the number of pixels in the given region.
+    /// at the top left corner and continuing, row first, to the bottom right corner. The colors are
+    /// collected into a buffer first, use `set_pixels` or `set_pixels_lvgl` when the colors are already in
+    /// a slice.
     ///
     /// # Arguments
     ///
@@ -449,20 +383,218 @@ impl LcdPanel {
     where
         T: IntoIterator<Item = lvgl::Color>,
     {
-        let iter = UnsafeCell::new(colors);
-        unsafe {
-            esp!(esp_lcd_panel_draw_bitmap(
-                self.panel,
-                sx,
-                sy,
-                ex,
-                ey,
-                &iter as *const _ as _, //colors.as_ptr() as *const c_void,
-            ))?;
-        };
+        let len = ((ex - sx).max(0) * (ey - sy).max(0)) as usize;
+        let pixels: Vec<u16> = colors
+            .into_iter()
+            .take(len)
+            .map(|color| Rgb565::from(color).into_storage())
+            .collect();
+
+        self.set_pixels(sx, sy, ex, ey, &pixels)
+    }
+}
+
+impl DisplayBackend for LcdPanel {
+    type Error = EspError;
+
+    fn resolution(&self) -> (u32, u32) {
+        self.display_size()
+    }
+
+    fn color_format(&self) -> ColorFormat {
+        self.color_mode.into()
+    }
+
+    // With refresh_on_demand the panel is only sent a frame after Lvgl has drawn something
+    fn flush_ready(&mut self) -> Result<(), Self::Error> {
+        self.refresh_if_dirty()?;
+        Ok(())
+    }
+
+    fn draw_region(
+        &mut self,
+        sx: i32,
+        sy: i32,
+        ex: i32,
+        ey: i32,
+        pixels: &[Rgb565],
+    ) -> Result<(), Self::Error> {
+        let pixels =
+            unsafe { core::slice::from_raw_parts(pixels.as_ptr() as *const u16, pixels.len()) };
+
+        self.set_pixels(sx, sy, ex, ey, pixels)
+    }
+}
+
+// Draw with embedded-graphics eg a boot splash screen before Lvgl starts. The coordinates are in the rotated
+// display orientation, do not draw while Lvgl is flushing to the panel.
+impl OriginDimensions for LcdPanel {
+    fn size(&self) -> Size {
+        let (width, height) = self.display_size();
+        Size::new(width, height)
+    }
+}
+
+impl DrawTarget for LcdPanel {
+    type Color = Rgb565;
+    type Error = EspError;
+
+    // Every pixel is drawn on its own, fill_contiguous and fill_solid draw areas a band of lines at a time
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let bounds = self.bounding_box();
+        for Pixel(point, color) in pixels {
+            if bounds.contains(point) {
+                self.set_pixels(
+                    point.x,
+                    point.y,
+                    point.x + 1,
+                    point.y + 1,
+                    &[color.into_storage()],
+                )?;
+            }
+        }
 
         Ok(())
     }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        let drawable = area.intersection(&self.bounding_box());
+        if drawable.is_zero_sized() {
+            return Ok(());
+        }
+
+        let (sx, width) = (drawable.top_left.x, drawable.size.width as usize);
+        let mut sy = drawable.top_left.y;
+        let mut band = core::mem::take(&mut self.fill_buffer);
+        band.clear();
+
+        // The colors are for the whole area, only the colors inside the display are drawn
+        let mut result = Ok(());
+        for (point, color) in area.points().zip(colors) {
+            if drawable.contains(point) {
+                band.push(color.into_storage());
+                if band.len() == width * FILL_BAND_LINES {
+                    result = self.set_pixels(
+                        sx,
+                        sy,
+                        sx + width as i32,
+                        sy + FILL_BAND_LINES as i32,
+                        &band,
+                    );
+                    sy += FILL_BAND_LINES as i32;
+                    band.clear();
+                    if result.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        // The last band, the last line is not complete when there are fewer colors than pixels in the area
+        if result.is_ok() && !band.is_empty() {
+            let lines = band.len() / width;
+            let rest = band.len() % width;
+            if lines > 0 {
+                result = self.set_pixels(sx, sy, sx + width as i32, sy + lines as i32, &band);
+            }
+            if result.is_ok() && rest > 0 {
+                let sy = sy + lines as i32;
+                result = self.set_pixels(sx, sy, sx + rest as i32, sy + 1, &band[lines * width..]);
+            }
+        }
+
+        self.fill_buffer = band;
+        result
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = area.intersection(&self.bounding_box());
+        if area.is_zero_sized() {
+            return Ok(());
+        }
+
+        let (width, height) = (area.size.width as usize, area.size.height as usize);
+        let lines = height.min(FILL_BAND_LINES);
+        let mut band = core::mem::take(&mut self.fill_buffer);
+        band.clear();
+        band.resize(width * lines, color.into_storage());
+
+        // The same band of pixels is drawn down the area
+        let (sx, ex) = (area.top_left.x, area.top_left.x + width as i32);
+        let ey = area.top_left.y + height as i32;
+        let mut result = Ok(());
+        for sy in (area.top_left.y..ey).step_by(lines) {
+            result = self.set_pixels(sx, sy, ex, (sy + lines as i32).min(ey), &band);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        self.fill_buffer = band;
+        result
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.fill_solid(&self.bounding_box(), color)
+    }
+}
+
+/// A frame buffer of the RGB panel, see `LcdPanel::frame_buffer`
+pub struct PanelFrameBuffer<'a> {
+    panel: esp_lcd_panel_handle_t,
+    width: usize,
+    height: usize,
+    pixels: &'a mut [Rgb565],
+}
+
+impl PanelFrameBuffer<'_> {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+}
+
+impl Deref for PanelFrameBuffer<'_> {
+    type Target = [Rgb565];
+
+    fn deref(&self) -> &Self::Target {
+        self.pixels
+    }
+}
+
+impl DerefMut for PanelFrameBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.pixels
+    }
+}
+
+impl Drop for PanelFrameBuffer<'_> {
+    fn drop(&mut self) {
+        // Drawing a frame buffer onto the panel does not copy anything, the driver writes the cache back to the
+        // PSRAM and switches to the frame buffer
+        let result = esp!(unsafe {
+            esp_lcd_panel_draw_bitmap(
+                self.panel,
+                0,
+                0,
+                self.width as i32,
+                self.height as i32,
+                self.pixels.as_ptr() as *const c_void,
+            )
+        });
+        if let Err(e) = result {
+            warn!("Failed to write back frame buffer: {}", e);
+        }
+    }
 }
 
 impl Drop for LcdPanel {
@@ -0,0 +1,28 @@
+//! The modules shared by the ESP32S3 firmware (main.rs) and the desktop simulator (bin/simulator.rs).
+//! The modules that need ESP-IDF are only built for the ESP32S3.
+pub mod backlight;
+pub mod board;
+pub mod calibration;
+pub mod display_backend;
+pub mod draw_buffer;
+pub mod framebuffer;
+pub mod gesture;
+#[cfg(feature = "simulator")]
+pub mod golden;
+pub mod gt911;
+#[cfg(target_os = "espidf")]
+pub mod lcd_panel;
+pub mod lvgl_fs;
+pub mod panel_config;
+pub mod rotation;
+pub mod settings;
+#[cfg(feature = "simulator")]
+pub mod simulator;
+#[cfg(target_os = "espidf")]
+pub mod storage;
+#[cfg(target_os = "espidf")]
+pub mod touch_interrupt;
+pub mod touch_record;
+pub mod ui;
+#[cfg(target_os = "espidf")]
+pub mod vsync_flush;
@@ -0,0 +1,445 @@
+//! Lvgl file system driver for a directory.
+//!
+//! Registers a drive letter with Lvgl that maps Lvgl paths onto a directory opened with std::fs, eg with the
+//! drive letter 'S' and the directory "/storage" Lvgl opens "S:/images/logo.bin" as "/storage/images/logo.bin".
+//! On the ESP32S3 the directory is the mount point of the app_storage partition (see storage.rs), on a PC any
+//! directory can be used.
+use core::ffi::{c_char, c_void, CStr};
+use core::fmt;
+use std::fs::{File, OpenOptions, ReadDir};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Size of the buffer a file name is written to when reading a directory with `lv_fs_dir_read`. Lvgl 8 does not
+/// pass the size of the buffer to the driver, the Lvgl examples and drivers use a 256 byte buffer so callers of
+/// `lv_fs_dir_read` on this drive must pass a buffer of at least this size.
+pub const FILE_NAME_BUF_LEN: usize = 256;
+
+// Maximum length in bytes of a file name returned when reading a directory, without the terminating 0
+const MAX_FILE_NAME_LEN: usize = FILE_NAME_BUF_LEN - 1;
+
+/// Errors returned when registering a drive
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FsDriverError {
+    /// Lvgl drive letters are 'A' to 'Z'
+    InvalidLetter(char),
+    AlreadyRegistered(char),
+}
+
+impl fmt::Display for FsDriverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsDriverError::InvalidLetter(letter) => {
+                write!(f, "drive letter '{}' is not an upper case letter", letter)
+            }
+            FsDriverError::AlreadyRegistered(letter) => {
+                write!(f, "drive letter '{}' is already registered", letter)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FsDriverError {}
+
+/// Registers `letter` as an Lvgl drive for the files under `root`.
+///
+/// Must be called after `lvgl::init`, the drive stays registered for the life of the program.
+pub fn register(letter: char, root: impl Into<PathBuf>) -> Result<(), FsDriverError> {
+    if !letter.is_ascii_uppercase() {
+        return Err(FsDriverError::InvalidLetter(letter));
+    }
+
+    unsafe {
+        if !lvgl_sys::lv_fs_get_drv(letter as c_char).is_null() {
+            return Err(FsDriverError::AlreadyRegistered(letter));
+        }
+
+        // Lvgl keeps a pointer to the driver so it is never freed
+        let drv: &'static mut lvgl_sys::lv_fs_drv_t = Box::leak(Box::new(core::mem::zeroed()));
+        lvgl_sys::lv_fs_drv_init(drv);
+
+        drv.letter = letter as c_char;
+        drv.open_cb = Some(open_cb);
+        drv.close_cb = Some(close_cb);
+        drv.read_cb = Some(read_cb);
+        drv.write_cb = Some(write_cb);
+        drv.seek_cb = Some(seek_cb);
+        drv.tell_cb = Some(tell_cb);
+        drv.dir_open_cb = Some(dir_open_cb);
+        drv.dir_read_cb = Some(dir_read_cb);
+        drv.dir_close_cb = Some(dir_close_cb);
+        drv.user_data = Box::into_raw(Box::new(root.into())) as *mut c_void;
+
+        lvgl_sys::lv_fs_drv_register(drv);
+    }
+
+    Ok(())
+}
+
+const RES_OK: lvgl_sys::lv_fs_res_t = lvgl_sys::LV_FS_RES_OK as lvgl_sys::lv_fs_res_t;
+const RES_FS_ERR: lvgl_sys::lv_fs_res_t = lvgl_sys::LV_FS_RES_FS_ERR as lvgl_sys::lv_fs_res_t;
+const RES_INV_PARAM: lvgl_sys::lv_fs_res_t = lvgl_sys::LV_FS_RES_INV_PARAM as lvgl_sys::lv_fs_res_t;
+
+// The path on the std file system of a path on the drive, Lvgl has already removed the "S:" from the path
+unsafe fn full_path(drv: *mut lvgl_sys::lv_fs_drv_t, path: *const c_char) -> Option<PathBuf> {
+    let root = &*((*drv).user_data as *const PathBuf);
+    let path = CStr::from_ptr(path).to_str().ok()?;
+    drive_path(root, path)
+}
+
+// Joins a drive path to the root directory, None if the path has ".." components that could leave the root
+fn drive_path(root: &Path, path: &str) -> Option<PathBuf> {
+    let path = Path::new(path.trim_start_matches(['/', '\\']));
+    if !path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return None;
+    }
+
+    Some(root.join(path))
+}
+
+// Opens a file the same way as the Lvgl stdio driver, write only truncates the file
+unsafe extern "C" fn open_cb(
+    drv: *mut lvgl_sys::lv_fs_drv_t,
+    path: *const c_char,
+    mode: lvgl_sys::lv_fs_mode_t,
+) -> *mut c_void {
+    let Some(path) = full_path(drv, path) else {
+        return core::ptr::null_mut();
+    };
+
+    let read = mode as u32 & lvgl_sys::LV_FS_MODE_RD != 0;
+    let write = mode as u32 & lvgl_sys::LV_FS_MODE_WR != 0;
+    let file = OpenOptions::new()
+        .read(read)
+        .write(write)
+        .create(write && !read)
+        .truncate(write && !read)
+        .open(path);
+
+    match file {
+        Ok(file) => Box::into_raw(Box::new(file)) as *mut c_void,
+        Err(_) => core::ptr::null_mut(),
+    }
+}
+
+unsafe extern "C" fn close_cb(
+    _drv: *mut lvgl_sys::lv_fs_drv_t,
+    file_p: *mut c_void,
+) -> lvgl_sys::lv_fs_res_t {
+    drop(Box::from_raw(file_p as *mut File));
+    RES_OK
+}
+
+unsafe extern "C" fn read_cb(
+    _drv: *mut lvgl_sys::lv_fs_drv_t,
+    file_p: *mut c_void,
+    buf: *mut c_void,
+    btr: u32,
+    br: *mut u32,
+) -> lvgl_sys::lv_fs_res_t {
+    let file = &mut *(file_p as *mut File);
+    let buf = core::slice::from_raw_parts_mut(buf as *mut u8, btr as usize);
+
+    // Fewer bytes than requested are only returned at the end of the file
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(_) => return RES_FS_ERR,
+        }
+    }
+
+    *br = len as u32;
+    RES_OK
+}
+
+unsafe extern "C" fn write_cb(
+    _drv: *mut lvgl_sys::lv_fs_drv_t,
+    file_p: *mut c_void,
+    buf: *const c_void,
+    btw: u32,
+    bw: *mut u32,
+) -> lvgl_sys::lv_fs_res_t {
+    let file = &mut *(file_p as *mut File);
+    let buf = core::slice::from_raw_parts(buf as *const u8, btw as usize);
+
+    match file.write_all(buf) {
+        Ok(()) => {
+            *bw = btw;
+            RES_OK
+        }
+        Err(_) => RES_FS_ERR,
+    }
+}
+
+// Lvgl passes the offset as u32, a negative offset from the current position or the end of the file is
+// wrapped to u32 (Lvgl adds it to its own u32 position with the same wrap around) so it is read back as i32
+unsafe extern "C" fn seek_cb(
+    _drv: *mut lvgl_sys::lv_fs_drv_t,
+    file_p: *mut c_void,
+    pos: u32,
+    whence: lvgl_sys::lv_fs_whence_t,
+) -> lvgl_sys::lv_fs_res_t {
+    let file = &mut *(file_p as *mut File);
+    let pos = match whence {
+        lvgl_sys::lv_fs_whence_t_LV_FS_SEEK_SET => SeekFrom::Start(pos as u64),
+        lvgl_sys::lv_fs_whence_t_LV_FS_SEEK_CUR => SeekFrom::Current(pos as i32 as i64),
+        lvgl_sys::lv_fs_whence_t_LV_FS_SEEK_END => SeekFrom::End(pos as i32 as i64),
+        _ => return RES_INV_PARAM,
+    };
+
+    match file.seek(pos) {
+        Ok(_) => RES_OK,
+        Err(_) => RES_FS_ERR,
+    }
+}
+
+unsafe extern "C" fn tell_cb(
+    _drv: *mut lvgl_sys::lv_fs_drv_t,
+    file_p: *mut c_void,
+    pos_p: *mut u32,
+) -> lvgl_sys::lv_fs_res_t {
+    let file = &mut *(file_p as *mut File);
+
+    match file.stream_position() {
+        Ok(pos) => {
+            *pos_p = pos as u32;
+            RES_OK
+        }
+        Err(_) => RES_FS_ERR,
+    }
+}
+
+unsafe extern "C" fn dir_open_cb(
+    drv: *mut lvgl_sys::lv_fs_drv_t,
+    path: *const c_char,
+) -> *mut c_void {
+    match full_path(drv, path).map(std::fs::read_dir) {
+        Some(Ok(dir)) => Box::into_raw(Box::new(dir)) as *mut c_void,
+        _ => core::ptr::null_mut(),
+    }
+}
+
+// Lvgl expects directory names to start with '/' and an empty name after the last entry. `fn_` points to a buffer
+// of `FILE_NAME_BUF_LEN` bytes, longer names are truncated
+unsafe extern "C" fn dir_read_cb(
+    _drv: *mut lvgl_sys::lv_fs_drv_t,
+    rddir_p: *mut c_void,
+    fn_: *mut c_char,
+) -> lvgl_sys::lv_fs_res_t {
+    let dir = &mut *(rddir_p as *mut ReadDir);
+
+    let mut name = Vec::with_capacity(MAX_FILE_NAME_LEN);
+    match dir.next() {
+        Some(Ok(entry)) => {
+            if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                name.push(b'/');
+            }
+            name.extend_from_slice(entry.file_name().as_encoded_bytes());
+            name.truncate(MAX_FILE_NAME_LEN);
+        }
+        Some(Err(_)) => return RES_FS_ERR,
+        None => {}
+    }
+
+    core::ptr::copy_nonoverlapping(name.as_ptr(), fn_ as *mut u8, name.len());
+    *fn_.add(name.len()) = 0;
+    RES_OK
+}
+
+unsafe extern "C" fn dir_close_cb(
+    _drv: *mut lvgl_sys::lv_fs_drv_t,
+    rddir_p: *mut c_void,
+) -> lvgl_sys::lv_fs_res_t {
+    drop(Box::from_raw(rddir_p as *mut ReadDir));
+    RES_OK
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drive_paths() {
+        let root = Path::new("/storage");
+        assert_eq!(
+            drive_path(root, "/images/logo.bin"),
+            Some(PathBuf::from("/storage/images/logo.bin"))
+        );
+        assert_eq!(
+            drive_path(root, "fonts/./font.bin"),
+            Some(PathBuf::from("/storage/fonts/font.bin"))
+        );
+        assert_eq!(drive_path(root, ""), Some(PathBuf::from("/storage")));
+
+        assert_eq!(drive_path(root, "../nvs"), None);
+        assert_eq!(drive_path(root, "/images/../../nvs"), None);
+    }
+
+    // A driver for a new empty directory under the temporary directory, the directory is removed when dropped
+    struct TestDrive {
+        drv: lvgl_sys::lv_fs_drv_t,
+        root: Box<PathBuf>,
+    }
+
+    impl TestDrive {
+        fn new(name: &str) -> Self {
+            let root =
+                std::env::temp_dir().join(format!("lvgl_fs_{}_{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&root);
+            std::fs::create_dir_all(&root).unwrap();
+
+            let mut root = Box::new(root);
+            let mut drv: lvgl_sys::lv_fs_drv_t = unsafe { core::mem::zeroed() };
+            drv.user_data = &mut *root as *mut PathBuf as *mut c_void;
+            Self { drv, root }
+        }
+
+        fn open(&mut self, path: &CStr, mode: u32) -> *mut c_void {
+            unsafe { open_cb(&mut self.drv, path.as_ptr(), mode as lvgl_sys::lv_fs_mode_t) }
+        }
+
+        fn read_dir(&mut self, path: &CStr) -> Vec<String> {
+            unsafe {
+                let dir = dir_open_cb(&mut self.drv, path.as_ptr());
+                assert!(!dir.is_null());
+
+                let mut names = Vec::new();
+                loop {
+                    let mut buf = [0x55 as c_char; FILE_NAME_BUF_LEN];
+                    assert_eq!(dir_read_cb(&mut self.drv, dir, buf.as_mut_ptr()), RES_OK);
+                    let name = CStr::from_ptr(buf.as_ptr()).to_str().unwrap();
+                    if name.is_empty() {
+                        break;
+                    }
+                    names.push(name.to_string());
+                }
+                assert_eq!(dir_close_cb(&mut self.drv, dir), RES_OK);
+
+                names.sort();
+                names
+            }
+        }
+    }
+
+    impl Drop for TestDrive {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&*self.root);
+        }
+    }
+
+    #[test]
+    fn write_read_seek_tell() {
+        let mut drive = TestDrive::new("files");
+        let mode_wr = lvgl_sys::LV_FS_MODE_WR;
+        let mode_rd = lvgl_sys::LV_FS_MODE_RD;
+
+        unsafe {
+            assert!(drive.open(c"/missing.bin", mode_rd).is_null());
+            assert!(drive.open(c"/../escape.bin", mode_wr).is_null());
+
+            let file = drive.open(c"/data.bin", mode_wr);
+            assert!(!file.is_null());
+            let data: Vec<u8> = (0..100).collect();
+            let mut written = 0;
+            assert_eq!(
+                write_cb(
+                    &mut drive.drv,
+                    file,
+                    data.as_ptr() as *const c_void,
+                    100,
+                    &mut written
+                ),
+                RES_OK
+            );
+            assert_eq!(written, 100);
+            assert_eq!(close_cb(&mut drive.drv, file), RES_OK);
+            assert_eq!(std::fs::read(drive.root.join("data.bin")).unwrap(), data);
+
+            let file = drive.open(c"data.bin", mode_rd);
+            assert!(!file.is_null());
+            let mut buf = [0u8; 8];
+            let mut read = 0;
+            let mut pos = 0;
+
+            let mut read_at = |offset: u32, whence: lvgl_sys::lv_fs_whence_t, pos: &mut u32| {
+                assert_eq!(seek_cb(&mut drive.drv, file, offset, whence), RES_OK);
+                assert_eq!(tell_cb(&mut drive.drv, file, pos), RES_OK);
+                assert_eq!(
+                    read_cb(
+                        &mut drive.drv,
+                        file,
+                        buf.as_mut_ptr() as *mut c_void,
+                        4,
+                        &mut read
+                    ),
+                    RES_OK
+                );
+                (read, buf[0])
+            };
+            assert_eq!(
+                read_at(10, lvgl_sys::lv_fs_whence_t_LV_FS_SEEK_SET, &mut pos),
+                (4, 10)
+            );
+            assert_eq!(pos, 10);
+            assert_eq!(
+                read_at(6, lvgl_sys::lv_fs_whence_t_LV_FS_SEEK_CUR, &mut pos),
+                (4, 20)
+            );
+            assert_eq!(pos, 20);
+            // Backward seeks are negative offsets wrapped to u32
+            assert_eq!(
+                read_at(
+                    -12i32 as u32,
+                    lvgl_sys::lv_fs_whence_t_LV_FS_SEEK_CUR,
+                    &mut pos
+                ),
+                (4, 12)
+            );
+            assert_eq!(pos, 12);
+            assert_eq!(
+                read_at(
+                    -2i32 as u32,
+                    lvgl_sys::lv_fs_whence_t_LV_FS_SEEK_END,
+                    &mut pos
+                ),
+                (2, 98)
+            );
+            assert_eq!(pos, 98);
+            assert_eq!(
+                read_at(0, lvgl_sys::lv_fs_whence_t_LV_FS_SEEK_END, &mut pos),
+                (0, 98)
+            );
+
+            assert_eq!(seek_cb(&mut drive.drv, file, 0, 7), RES_INV_PARAM);
+            assert_eq!(close_cb(&mut drive.drv, file), RES_OK);
+        }
+    }
+
+    #[test]
+    fn read_directory() {
+        let mut drive = TestDrive::new("dir");
+        std::fs::create_dir(drive.root.join("images")).unwrap();
+        std::fs::write(drive.root.join("images/logo.bin"), b"logo").unwrap();
+        std::fs::write(drive.root.join("font.bin"), b"font").unwrap();
+        let long_name = "n".repeat(MAX_FILE_NAME_LEN);
+        std::fs::create_dir(drive.root.join(&long_name)).unwrap();
+
+        // Directory names start with '/', the long name is truncated to fit in the buffer
+        let mut truncated = format!("/{}", long_name);
+        truncated.truncate(MAX_FILE_NAME_LEN);
+        assert_eq!(
+            drive.read_dir(c"/"),
+            vec!["/images".to_string(), truncated, "font.bin".to_string()]
+        );
+        assert_eq!(drive.read_dir(c"/images"), vec!["logo.bin".to_string()]);
+
+        unsafe {
+            assert!(dir_open_cb(&mut drive.drv, c"/missing".as_ptr()).is_null());
+        }
+    }
+}
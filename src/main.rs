@@ -1,15 +1,16 @@
-// Place pub before mod otherwise youu will get warnings about multiple methods not used in lcd_panel
-pub mod gt911;
-pub mod lcd_panel;
-
+#[cfg(target_os = "espidf")]
 use log::*;
 
-use cstr_core::CString;
-use std::time::Instant;
-use std::{cell::RefCell, thread};
+#[cfg(target_os = "espidf")]
+use std::sync::mpsc;
+#[cfg(target_os = "espidf")]
+use std::time::{Duration, Instant};
+#[cfg(target_os = "espidf")]
+use std::{cell::RefCell, io, thread};
 
+#[cfg(target_os = "espidf")]
 use esp_idf_svc::hal::{
-    delay::{Ets, FreeRtos},
+    delay::{Delay, FreeRtos},
     gpio::{self, PinDriver},
     i2c::{I2cConfig, I2cDriver},
     ledc::{
@@ -19,20 +20,60 @@ use esp_idf_svc::hal::{
     peripherals::Peripherals,
     units::FromValueType,
 };
-
-use lvgl::style::Style;
-use lvgl::widgets::{Btn, Label};
-use lvgl::{Align, Color, Display, DrawBuffer, Part, Widget};
-
-use embedded_graphics_core::prelude::Point;
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs};
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::sys::{heap_caps_get_free_size, MALLOC_CAP_INTERNAL, MALLOC_CAP_SPIRAM};
+
+#[cfg(target_os = "espidf")]
+use lvgl::Display;
+
+#[cfg(target_os = "espidf")]
+use embedded_graphics_core::pixelcolor::{Rgb565, RgbColor};
+#[cfg(target_os = "espidf")]
+use embedded_graphics_core::prelude::{DrawTarget, Point};
+#[cfg(target_os = "espidf")]
 use lvgl::input_device::{
     pointer::{Pointer, PointerInputData},
     InputDriver,
 };
 
-use crate::gt911::{TouchState, GT911};
-use crate::lcd_panel::{LcdPanel, PanelConfig, PanelFlagsConfig, TimingFlagsConfig, TimingsConfig};
+#[cfg(target_os = "espidf")]
+use rust_esp32s3_lvgl_clickme::backlight::{Backlight, BacklightConfig};
+#[cfg(target_os = "espidf")]
+use rust_esp32s3_lvgl_clickme::board::{Board, TouchController};
+#[cfg(target_os = "espidf")]
+use rust_esp32s3_lvgl_clickme::calibration::{run_calibration, CalibrationPoints};
+#[cfg(target_os = "espidf")]
+use rust_esp32s3_lvgl_clickme::draw_buffer::{self, BufferPlacement, DrawBufferConfig};
+#[cfg(target_os = "espidf")]
+use rust_esp32s3_lvgl_clickme::gesture::{GestureConfig, GestureRecognizer};
+#[cfg(target_os = "espidf")]
+use rust_esp32s3_lvgl_clickme::gt911::{TouchPoints, TouchState, GT911};
+#[cfg(target_os = "espidf")]
+use rust_esp32s3_lvgl_clickme::lcd_panel::{ColorMode, LcdPanel};
+#[cfg(target_os = "espidf")]
+use rust_esp32s3_lvgl_clickme::rotation::Rotation;
+#[cfg(target_os = "espidf")]
+use rust_esp32s3_lvgl_clickme::settings::SettingsStore;
+#[cfg(target_os = "espidf")]
+use rust_esp32s3_lvgl_clickme::storage::{Storage, StorageConfig};
+#[cfg(target_os = "espidf")]
+use rust_esp32s3_lvgl_clickme::touch_interrupt::TouchInterrupt;
+#[cfg(target_os = "espidf")]
+use rust_esp32s3_lvgl_clickme::touch_record::{TouchRecorder, TouchReplay};
+#[cfg(target_os = "espidf")]
+use rust_esp32s3_lvgl_clickme::ui::create_ui;
+#[cfg(target_os = "espidf")]
+use rust_esp32s3_lvgl_clickme::vsync_flush;
+
+// The simulator and the golden image tests build every binary of the package on the PC
+#[cfg(not(target_os = "espidf"))]
+fn main() {
+    eprintln!("The firmware only runs on the ESP32S3, run the simulator binary on a PC");
+}
 
+#[cfg(target_os = "espidf")]
 fn main() -> anyhow::Result<()> {
     // It is necessary to call this function once. Otherwise some patches to the runtime
     // implemented by esp-idf-sys might not link properly. See https://github.com/esp-rs/esp-idf-template/issues/71
@@ -44,18 +85,78 @@ fn main() -> anyhow::Result<()> {
     info!("=================== Starting APP! =========================");
 
     let peripherals = Peripherals::take()?;
+    let nvs_partition = EspDefaultNvsPartition::take()?;
 
+    // The GPIOs are taken from the board profile, nothing else uses the pins
     #[allow(unused)]
     let pins = peripherals.pins;
 
+    //============================================================================================================
+    //               Select the board
+    //============================================================================================================
+    // The board is selected with the board-* cargo features. Storing a board name (eg "8048s050") in NVS under
+    // the "device" namespace and "board" key selects the board at runtime so one firmware can run on all boards.
+    let board = EspNvs::new(nvs_partition.clone(), "device", true)
+        .ok()
+        .and_then(|nvs| {
+            let mut buf = [0u8; 16];
+            nvs.get_str("board", &mut buf)
+                .ok()
+                .flatten()
+                .and_then(Board::from_name)
+        })
+        .unwrap_or_else(Board::selected);
+    let profile = board.profile();
+    info!("======== Board {} ==========", board);
+
+    //============================================================================================================
+    //               Load the settings
+    //============================================================================================================
+    // The settings are saved to NVS under the "settings" namespace, the defaults are used until they are saved
+    info!("======== Load Settings ==========");
+    let mut settings_store =
+        SettingsStore::new(EspNvs::new(nvs_partition.clone(), "settings", true)?);
+    let settings = match settings_store.load() {
+        Ok(settings) => settings,
+        Err(e) => {
+            warn!("Failed to load settings, using the defaults: {}", e);
+            None
+        }
+    };
+    let mut settings = settings.unwrap_or_default();
+    info!(
+        "Settings: brightness {}%, rotation {}, screen timeout {:?}",
+        settings.brightness,
+        settings.rotation.degrees(),
+        settings.screen_timeout
+    );
+
+    //============================================================================================================
+    //               Mount the app_storage partition
+    //============================================================================================================
+    // Images and fonts copied onto the app_storage FAT partition are loaded by Lvgl from "S:/..." paths
+    info!("======== Mount Storage ==========");
+    let storage = match Storage::mount(&StorageConfig::new()) {
+        Ok(storage) => {
+            info!("Storage mounted on {}", storage.mount_point());
+            Some(storage)
+        }
+        Err(e) => {
+            warn!("Failed to mount storage: {}", e);
+            None
+        }
+    };
+
     //============================================================================================================
     //               Create the I2C to communicate with the touchscreen controller
     //============================================================================================================
     info!("======== Create I2C ==========");
     let i2c = peripherals.i2c0;
-    let sda = pins.gpio19;
-    let scl = pins.gpio20;
-    let config = I2cConfig::new().baudrate(100.kHz().into());
+    // SAFETY: `peripherals.pins` is never used and the board profile gives each GPIO to one signal, so each pin is
+    // only created once and only used by the driver it is passed to
+    let sda = unsafe { gpio::AnyIOPin::new(profile.touch.sda_gpio_num) };
+    let scl = unsafe { gpio::AnyIOPin::new(profile.touch.scl_gpio_num) };
+    let config = I2cConfig::new().baudrate(profile.touch.i2c_frequency_hz.Hz());
     let i2c = I2cDriver::new(i2c, sda, scl, &config)?;
 
     //============================================================================================================
@@ -63,64 +164,289 @@ fn main() -> anyhow::Result<()> {
     //============================================================================================================
 
     info!("========== Create LedcDriver ==========");
-    let mut channel = LedcDriver::new(
-        peripherals.ledc.channel0,
-        LedcTimerDriver::new(
-            peripherals.ledc.timer0,
-            &TimerConfig::new().frequency(25.kHz().into()),
-        )
-        .unwrap(),
-        pins.gpio2,
-    )?;
-    //channel.set_duty(channel.get_max_duty() / 2)?;
-    channel.set_duty(channel.get_max_duty())?;
-    info!("Backlight turned on");
+    // The time used by the backlight fades and inactivity timeout
+    let app_start = Instant::now();
+    let backlight = if profile.backlight_gpio_num >= 0 {
+        let channel = LedcDriver::new(
+            peripherals.ledc.channel0,
+            LedcTimerDriver::new(
+                peripherals.ledc.timer0,
+                &TimerConfig::new().frequency(25.kHz().into()),
+            )
+            .unwrap(),
+            // SAFETY: the backlight pin is only used by the LEDC driver
+            unsafe { gpio::AnyOutputPin::new(profile.backlight_gpio_num) },
+        )?;
+        // The backlight is dimmed half way through the screen timeout and turned off at the end of it
+        let config = BacklightConfig::new()
+            .dim_timeout(settings.screen_timeout.map(|timeout| timeout / 2))
+            .off_timeout(settings.screen_timeout);
+        let mut backlight = Backlight::new(channel, config, app_start.elapsed())?;
+        backlight.set_brightness(settings.brightness, Duration::ZERO, app_start.elapsed());
+        info!("Backlight turned on");
+        Some(RefCell::new(backlight))
+    } else {
+        info!("Backlight is not controlled on this board");
+        None
+    };
 
     //============================================================================================================
     //               Create thread for Lvgl and User Interface
     //============================================================================================================
-    // Stack size value - 50,000 for 10 lines, 60,000 for 12 lines
+    // The draw buffers are on the heap (see draw_buffer.rs) so the stack size does not depend on their lines
     let _lvgl_thread = thread::Builder::new().stack_size(24000).spawn(move || {
         // Initialize lvgl
         lvgl::init();
 
+        // The storage partition is the Lvgl S: drive
+        if let Some(storage) = &storage {
+            if let Err(e) = storage.register_lvgl_drive('S') {
+                warn!("Failed to register storage with Lvgl: {}", e);
+            }
+        }
+
         //=====================================================================================================
         //                         Create driver for the LCD Panel
         //=====================================================================================================
-        let mut lcd_panel = LcdPanel::new(
-            &PanelConfig::new(),
-            &PanelFlagsConfig::new(),
-            &TimingsConfig::new(),
-            &TimingFlagsConfig::new(),
-        )
-        .unwrap();
+        // The display rotation from the settings, the touchscreen orientation follows the display rotation. Set
+        // the rotation to Rotation::Deg90 or Rotation::Deg270 for portrait mounted units.
+        let rotation = settings.rotation;
+
+        // The color mode of the panel frame buffer (RGB565, RGB565 with the bytes swapped or RGB888). Lvgl
+        // renders RGB565 and the pixels are converted when they are flushed to the panel. RGB888 panels are sent
+        // serially over an 8 bit bus, set data_width to 8 in the board profile. The ESP32S3 has 16 data lines so
+        // a 24 bit parallel panel can not be wired up as such, see the README.
+        const COLOR_MODE: ColorMode = ColorMode::Rgb565;
+        const _: () = assert!(
+            COLOR_MODE.lvgl_color_supported(lvgl_sys::LV_COLOR_DEPTH, lvgl_sys::LV_COLOR_16_SWAP),
+            "COLOR_MODE can not be flushed from what Lvgl renders, set LV_COLOR_DEPTH to 16 and LV_COLOR_16_SWAP \
+             to 0 in lv_conf.h"
+        );
+        let panel_config = profile.panel.color_mode(COLOR_MODE);
+
+        // Set TEAR_FREE to render into two frame buffers that are only switched at VSYNC (see vsync_flush.rs).
+        // This allocates a second frame buffer in PSRAM and Lvgl redraws the whole screen on every refresh.
+        // Lvgl renders straight into the frame buffers so tear free rendering can not be used with a rotation or
+        // a color mode other than RGB565.
+        const TEAR_FREE: bool = false;
+
+        // Set REFRESH_ON_DEMAND to only send a frame to the panel after Lvgl has drawn something instead of
+        // streaming the frame buffer all the time, this saves power on battery powered builds. Only use it with
+        // panels that keep the image between refreshes, most RGB panels fade or flicker without the stream so it
+        // is off by default. Tear free rendering needs the panel to be streamed.
+        const REFRESH_ON_DEMAND: bool = false;
+        let panel_flags = profile.panel_flags.refresh_on_demand(REFRESH_ON_DEMAND);
+
+        // The Lvgl draw buffers used when Lvgl does not render into the frame buffers. More lines redraw faster
+        // but use more memory, internal SRAM is the fastest and the buffers go to PSRAM when they do not fit.
+        // The LcdPanel flushes synchronously so a second buffer would only use memory.
+        let draw_buffer_config = DrawBufferConfig::new()
+            .lines(40)
+            .double_buffer(false)
+            .placement(BufferPlacement::InternalDma);
+
+        let panel_config = if TEAR_FREE
+            && !REFRESH_ON_DEMAND
+            && rotation == Rotation::Deg0
+            && COLOR_MODE == ColorMode::Rgb565
+        {
+            panel_config.num_fbs(2)
+        } else {
+            panel_config
+        };
+        let timings = profile.timings;
+
+        // Set BOUNCE_BUFFER to send the frame buffer to the panel through bounce buffers in internal SRAM, this
+        // stops the display drifting and flickering when Wi-Fi or flash writes are using the PSRAM bandwidth.
+        // Bounce buffers need the panel to be streamed.
+        const BOUNCE_BUFFER: bool = false;
+        let panel_config = if BOUNCE_BUFFER && !REFRESH_ON_DEMAND {
+            panel_config.bounce_buffer(&timings)
+        } else {
+            panel_config
+        };
+
+        // Catch configuration mistakes here with an explanation instead of an error code from ESP-IDF
+        let panel_timing = panel_config
+            .validate(&panel_flags, &timings)
+            .unwrap_or_else(|e| panic!("Invalid panel configuration: {}", e));
+        info!(
+            "Panel refresh rate {:.1} Hz, {} frame buffer(s) of {} bytes",
+            panel_timing.refresh_rate_hz, panel_timing.num_fbs, panel_timing.frame_size_bytes
+        );
+
+        let lcd_panel = RefCell::new(
+            LcdPanel::new(&panel_config, &panel_flags, &timings, &profile.timing_flags).unwrap(),
+        );
+        lcd_panel.borrow_mut().set_rotation(rotation);
+
+        // The LcdPanel is an embedded-graphics DrawTarget, clear the uninitialized frame buffer until Lvgl draws
+        // the first screen
+        lcd_panel.borrow_mut().clear(Rgb565::BLACK).unwrap();
+        lcd_panel.borrow_mut().refresh_if_dirty().unwrap();
 
         info!("=============  Registering Display ====================");
-        const HOR_RES: u32 = 800;
-        const VER_RES: u32 = 480;
-        const LINES: u32 = 4; // The number of lines (rows) that will be refreshed  was 12
-        let draw_buffer = DrawBuffer::<{ (HOR_RES * LINES) as usize }>::default();
-        let display = Display::register(draw_buffer, HOR_RES, VER_RES, |refresh| {
-            lcd_panel
-                .set_pixels_lvgl_color(
-                    refresh.area.x1.into(),
-                    refresh.area.y1.into(),
-                    (refresh.area.x2 + 1i16).into(),
-                    (refresh.area.y2 + 1i16).into(),
-                    refresh.colors.into_iter(),
+        // The display driver is registered with lvgl_sys instead of lvgl-rs Display::register, which takes a fixed
+        // size draw buffer and copies it onto the stack at every flush. With two frame buffers Lvgl renders
+        // straight into the frame buffers, otherwise into the heap allocated draw buffers which are flushed to the
+        // LcdPanel through its DisplayBackend.
+        if lcd_panel.borrow().frame_buffer_count() >= 2 {
+            info!("=============  Enabling VSYNC flushing ====================");
+            vsync_flush::register(&mut lcd_panel.borrow_mut()).unwrap();
+        } else {
+            // SAFETY: lcd_panel is declared before the Lvgl display so it is dropped after the display, it is not
+            // moved and the Lvgl thread never returns
+            let draw_buffer_info =
+                unsafe { draw_buffer::register(&draw_buffer_config, &lcd_panel) }.unwrap();
+            let (free_internal, free_psram) = unsafe {
+                (
+                    heap_caps_get_free_size(MALLOC_CAP_INTERNAL),
+                    heap_caps_get_free_size(MALLOC_CAP_SPIRAM),
                 )
-                .unwrap();
-        })
-        .unwrap();
+            };
+            info!(
+                "Draw buffers {}, {} bytes, free internal SRAM {} bytes, free PSRAM {} bytes",
+                draw_buffer_info,
+                draw_buffer_info.total_bytes(),
+                free_internal,
+                free_psram
+            );
+        }
+        // The display registered above is the default Lvgl display
+        let display = Display::default();
 
         //======================================================================================================
         //                          Create the driver for the Touchscreen
         //======================================================================================================
         info!("=============  Creating Touchscreen ====================");
-        //let gt911_touchscreen = RefCell::new(GT911::new(i2c, rst, Ets));
-        //gt911_touchscreen.borrow_mut().reset().unwrap();
         let touchscreen = RefCell::new(GT911::new(i2c));
-        reset_gt911(pins.gpio38.into());
+        let (panel_width, panel_height) = profile.resolution();
+        touchscreen
+            .borrow_mut()
+            .set_size(panel_height as u16, panel_width as u16);
+        touchscreen
+            .borrow_mut()
+            .set_orientation(rotation.orientation());
+        if profile.touch.controller == TouchController::Gt1151 {
+            info!("Reading the GT1151 with the GT911 driver");
+        }
+
+        // If the GT911 INT pin is connected to a GPIO (int_gpio_num in the board profile), the touchscreen is
+        // only read over I2C when the GT911 signals it has new touch data instead of on every Lvgl read.
+        // Without the INT pin the GT911 I2C address (0x5D or 0x14) is found by probing both addresses.
+        // SAFETY: the INT and RST pins are only used by the GT911 driver and the RST pin driver
+        let touch_int_pin = (profile.touch.int_gpio_num >= 0)
+            .then(|| unsafe { gpio::AnyIOPin::new(profile.touch.int_gpio_num) });
+
+        // Keep the RST pin driver alive so RST stays high. Without a RST pin the touchscreen is not reset and the
+        // address from the board profile is used.
+        let mut touch_rst = (profile.touch.rst_gpio_num >= 0).then(|| {
+            PinDriver::output(unsafe { gpio::AnyOutputPin::new(profile.touch.rst_gpio_num) })
+                .unwrap()
+        });
+        let mut touch_int = touch_int_pin.map(|pin| PinDriver::output(pin).unwrap());
+        match touch_rst.as_mut() {
+            Some(touch_rst) => {
+                if let Err(e) = touchscreen.borrow_mut().reset(
+                    touch_rst,
+                    touch_int.as_mut(),
+                    &mut Delay::new_default(),
+                ) {
+                    warn!("Failed to reset touchscreen: {}", e);
+                }
+            }
+            None => touchscreen
+                .borrow_mut()
+                .set_address(profile.touch.i2c_address),
+        }
+        info!(
+            "Touchscreen address: {:#04x}",
+            touchscreen.borrow().address()
+        );
+
+        // Release the INT pin so the GT911 can use it to signal new touch data
+        let touch_int = touch_int.map(|int| int.into_input().unwrap());
+        let touch_interrupt = touch_int.map(|pin| RefCell::new(TouchInterrupt::new(pin).unwrap()));
+
+        //======================================================================================================
+        //                          Load or run the Touchscreen calibration
+        //======================================================================================================
+        // Calibration must be done before the touchscreen is registered with Lvgl since both read the GT911. The
+        // calibration is saved with the settings. The calibration is skipped when the touchscreen does not answer
+        // and is given up when the targets are not all touched in time, the UI then starts without a calibration.
+        const CALIBRATION_TIMEOUT: Duration = Duration::from_secs(30);
+        let calibration = settings.touch_calibration.or_else(|| {
+            if let Err(e) = touchscreen.borrow_mut().read_product_id() {
+                warn!("Touchscreen not responding, skipping calibration: {}", e);
+                return None;
+            }
+
+            info!("=============  Calibrating Touchscreen ====================");
+            let size = touchscreen.borrow().oriented_size();
+            let result = run_calibration(
+                &display,
+                CalibrationPoints::Five,
+                size.width,
+                size.height,
+                CALIBRATION_TIMEOUT,
+                || {
+                    let last_tp = touchscreen.borrow().last_touch_point();
+                    let result = touchscreen.borrow_mut().read_touch();
+                    result.unwrap_or(TouchState::RELEASED(last_tp))
+                },
+            );
+
+            match result {
+                Ok(calibration) => {
+                    settings.touch_calibration = Some(calibration);
+                    if let Err(e) = settings_store.save(&settings) {
+                        warn!("Failed to save touch calibration: {}", e);
+                    }
+                    Some(calibration)
+                }
+                Err(e) => {
+                    warn!("Touch calibration failed: {}", e);
+                    None
+                }
+            }
+        });
+        info!("Touch calibration: {:?}", calibration);
+        touchscreen.borrow_mut().set_calibration(calibration);
+
+        // Gestures recognized from the touch points are sent to the Lvgl loop
+        let (gesture_tx, gesture_rx) = mpsc::channel();
+        let gesture_recognizer = RefCell::new(GestureRecognizer::new(GestureConfig::new()));
+
+        // Set TOUCH_RECORD to true to print the touch states to the console, copy them to a file to replay them
+        // later. Set TOUCH_REPLAY to Some(include_str!("../touch-traces/<file>.csv")) to replay a recording
+        // instead of reading the touchscreen.
+        const TOUCH_RECORD: bool = false;
+        const TOUCH_REPLAY: Option<&str> = None;
+        let touch_recorder =
+            TOUCH_RECORD.then(|| RefCell::new(TouchRecorder::new(io::stdout()).unwrap()));
+        let touch_replay = TOUCH_REPLAY
+            .map(|recording| RefCell::new(TouchReplay::from_reader(recording.as_bytes()).unwrap()));
+        let touch_start = Instant::now();
+
+        // A bad read on a noisy I2C bus should not bring down the Lvgl thread, report the touch as released at the
+        // last touch point and try again on the next read.
+        let read_touchscreen = || {
+            let result = match &touch_interrupt {
+                Some(touch_interrupt) => {
+                    let data_ready = touch_interrupt.borrow_mut().take_data_ready();
+                    touchscreen.borrow_mut().read_touch_if_ready(data_ready)
+                }
+                None => touchscreen.borrow_mut().read_touch(),
+            };
+
+            match result {
+                Ok(touch) => touch,
+                Err(e) => {
+                    warn!("Failed to read touchscreen: {}", e);
+                    TouchState::RELEASED(touchscreen.borrow().last_touch_point())
+                }
+            }
+        };
 
         // The read_touchscreen_cb is used by Lvgl to detect touchscreen presses and releases
         let read_touchscreen_cb = || {
@@ -134,7 +460,56 @@ fn main() -> anyhow::Result<()> {
             // The solution was to use interior mutability to solve this problem. This means wrapping your mutable reference
             // within a special type (RefCell), that can be shared via an immutable reference, but still allows mutability of its inner value.
 
-            let touch = touchscreen.borrow_mut().read_touch().unwrap();
+            let timestamp = touch_start.elapsed();
+            let touch = match &touch_replay {
+                Some(touch_replay) => {
+                    let touch = touch_replay.borrow_mut().state_at(timestamp);
+                    for gesture in gesture_recognizer
+                        .borrow_mut()
+                        .update_state(timestamp, touch)
+                    {
+                        let _ = gesture_tx.send(gesture);
+                    }
+                    touch
+                }
+                None => {
+                    // The gestures see a release whenever Lvgl does, including after a failed read
+                    let touch = read_touchscreen();
+                    let touches = match touch {
+                        TouchState::PRESSED(_) => touchscreen.borrow().last_touches(),
+                        TouchState::RELEASED(_) => TouchPoints::default(),
+                    };
+                    for gesture in gesture_recognizer
+                        .borrow_mut()
+                        .update(timestamp, touches.as_slice())
+                    {
+                        let _ = gesture_tx.send(gesture);
+                    }
+                    touch
+                }
+            };
+
+            if let Some(touch_recorder) = &touch_recorder {
+                if let Err(e) = touch_recorder.borrow_mut().record(timestamp, touch) {
+                    warn!("Failed to record touch: {}", e);
+                }
+            }
+
+            // A touch wakes the backlight, the touch that wakes it is not passed on to Lvgl
+            let touch = match (&backlight, touch) {
+                (Some(backlight), TouchState::PRESSED(tp)) => {
+                    if backlight.borrow_mut().touch(true, app_start.elapsed()) {
+                        touch
+                    } else {
+                        TouchState::RELEASED(tp)
+                    }
+                }
+                (Some(backlight), TouchState::RELEASED(_)) => {
+                    backlight.borrow_mut().touch(false, app_start.elapsed());
+                    touch
+                }
+                (None, _) => touch,
+            };
 
             match touch {
                 TouchState::PRESSED(tp) => {
@@ -166,45 +541,23 @@ fn main() -> anyhow::Result<()> {
         //                               Create the User Interface
         //=======================================================================================================
         info!("=============  Creating UI ====================");
-        // Create screen and widgets
-        let mut screen = display.get_scr_act().unwrap();
-        let mut screen_style = Style::default();
-        screen_style.set_bg_color(Color::from_rgb((0, 0, 139)));
-        screen_style.set_radius(0);
-        screen.add_style(Part::Main, &mut screen_style);
-
-        // Create the button
-        let mut button = Btn::create(&mut screen).unwrap();
-        button.set_align(Align::LeftMid, 30, 0);
-        button.set_size(180, 80);
-
-        // Create button label, align in center of button
-        let mut btn_lbl = Label::create(&mut button).unwrap();
-        btn_lbl.set_align(Align::Center, 0, 0);
-        btn_lbl.set_text(CString::new("Click me!").unwrap().as_c_str());
-
-        let mut btn_state = false;
-        button
-            .on_event(|_btn, event| {
-                if let lvgl::Event::Clicked = event {
-                    println!("Clicked Event");
-                    if btn_state {
-                        let nt = CString::new("Click me!").unwrap();
-                        btn_lbl.set_text(nt.as_c_str());
-                    } else {
-                        let nt = CString::new("Clicked!").unwrap();
-                        btn_lbl.set_text(nt.as_c_str());
-                    }
-                    btn_state = !btn_state;
-                }
-            })
-            .unwrap();
+        let _ui = create_ui(&display);
 
         loop {
             let start = Instant::now();
 
             lvgl::task_handler();
 
+            for gesture in gesture_rx.try_iter() {
+                info!("Gesture: {:?}", gesture);
+            }
+
+            if let Some(backlight) = &backlight {
+                if let Err(e) = backlight.borrow_mut().update(app_start.elapsed()) {
+                    warn!("Failed to set backlight: {}", e);
+                }
+            }
+
             // Keep the loop delay short so Lvgl can respond quickly to touchscreen presses and releases
             FreeRtos::delay_ms(30);
 
@@ -217,12 +570,3 @@ fn main() -> anyhow::Result<()> {
         FreeRtos::delay_ms(1000);
     }
 }
-
-// Reset the GT911 chip
-fn reset_gt911(rst_pin: gpio::AnyOutputPin) {
-    let mut rst = PinDriver::output(rst_pin).unwrap();
-    rst.set_low().unwrap();
-    Ets::delay_us(200);
-    rst.set_high().unwrap();
-    Ets::delay_ms(5);
-}
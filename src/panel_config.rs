@@ -0,0 +1,1037 @@
+//! The configuration of the RGB LCD panel.
+//!
+//! The configuration is plain Rust so it can be validated (see `PanelConfig::validate`) on the host as well as on
+//! the ESP32S3. The default configuration and timings are based upon the Aliexpress Esp32S-8048S070C
+//! development board.
+use core::fmt;
+
+#[cfg(target_os = "espidf")]
+pub use esp_idf_svc::sys::{
+    soc_periph_lcd_clk_src_t, soc_periph_lcd_clk_src_t_LCD_CLK_SRC_PLL160M,
+};
+
+// The ESP-IDF clock source type so the configuration can be built on the host, the value of the clock source is
+// only passed through to ESP-IDF
+#[cfg(not(target_os = "espidf"))]
+#[allow(non_camel_case_types)]
+pub type soc_periph_lcd_clk_src_t = u32;
+#[cfg(not(target_os = "espidf"))]
+#[allow(non_upper_case_globals)]
+pub const soc_periph_lcd_clk_src_t_LCD_CLK_SRC_PLL160M: soc_periph_lcd_clk_src_t = 0;
+
+/// The color format of the panel frame buffer
+///
+/// Lvgl always renders RGB565 (LV_COLOR_DEPTH 16), the pixels are converted to the panel color mode when they are
+/// flushed to the panel.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum ColorMode {
+    /// 16 bit RGB565
+    #[default]
+    Rgb565,
+    /// 16 bit RGB565 with the two bytes swapped, for panels with the data lines of the two bytes swapped
+    Rgb565Swapped,
+    /// 24 bit RGB888, stored blue byte first (the Lvgl 24 bit format) and sent over an 8 bit bus (serial RGB)
+    Rgb888,
+}
+
+impl ColorMode {
+    pub const fn bits_per_pixel(&self) -> usize {
+        match self {
+            ColorMode::Rgb565 | ColorMode::Rgb565Swapped => 16,
+            ColorMode::Rgb888 => 24,
+        }
+    }
+
+    /// Returns true if what Lvgl renders with `LV_COLOR_DEPTH` `color_depth` and `LV_COLOR_16_SWAP`
+    /// `color_16_swap` (lv_conf.h) can be flushed to a panel in this color mode.
+    ///
+    /// The flush converts RGB565 to the color mode, swapping the bytes for `Rgb565Swapped` and expanding the
+    /// colors for `Rgb888`, so Lvgl must render RGB565 without swapping the bytes itself.
+    pub const fn lvgl_color_supported(&self, color_depth: u32, color_16_swap: u32) -> bool {
+        matches!(
+            (self, color_depth, color_16_swap),
+            (
+                ColorMode::Rgb565 | ColorMode::Rgb565Swapped | ColorMode::Rgb888,
+                16,
+                0
+            )
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PanelConfig {
+    pub clk_src: soc_periph_lcd_clk_src_t,
+    pub data_width: usize,
+    pub bits_per_pixel: usize,
+    pub color_mode: ColorMode,
+    pub num_fbs: usize,
+    pub bounce_buffer_size_px: usize,
+    pub sram_trans_align: usize,
+    pub psram_trans_align: usize,
+    pub hsync_gpio_num: i32,
+    pub vsync_gpio_num: i32,
+    pub de_gpio_num: i32,
+    pub pclk_gpio_num: i32,
+    pub disp_gpio_num: i32,
+    pub data_gpio_nums: [i32; 16],
+}
+
+impl PanelConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn clk_src(mut self, source: soc_periph_lcd_clk_src_t) -> Self {
+        self.clk_src = source;
+        self
+    }
+
+    #[must_use]
+    pub fn data_width(mut self, data_width: usize) -> Self {
+        self.data_width = data_width;
+        self
+    }
+
+    #[must_use]
+    pub fn bits_per_pixel(mut self, bits_per_pixel: usize) -> Self {
+        self.bits_per_pixel = bits_per_pixel;
+        self
+    }
+
+    /// Sets the color mode and the matching bits_per_pixel, RGB888 also needs a data_width of 8
+    #[must_use]
+    pub fn color_mode(mut self, color_mode: ColorMode) -> Self {
+        self.color_mode = color_mode;
+        self.bits_per_pixel = color_mode.bits_per_pixel();
+        self
+    }
+
+    #[must_use]
+    pub fn num_fbs(mut self, num_fbs: usize) -> Self {
+        self.num_fbs = num_fbs;
+        self
+    }
+
+    #[must_use]
+    pub fn bounce_buffer_size_px(mut self, bounce_buffer_size_px: usize) -> Self {
+        self.bounce_buffer_size_px = bounce_buffer_size_px;
+        self
+    }
+
+    #[must_use]
+    pub fn sram_trans_align(mut self, sram_trans_align: usize) -> Self {
+        self.sram_trans_align = sram_trans_align;
+        self
+    }
+
+    #[must_use]
+    pub fn psram_trans_align(mut self, psram_trans_align: usize) -> Self {
+        self.psram_trans_align = psram_trans_align;
+        self
+    }
+
+    /// Uses bounce buffers sized for the resolution in `timings`, see `bounce_buffer_size_for`.
+    ///
+    /// With bounce buffers the frame buffer in PSRAM is copied by the CPU into two small DMA buffers in internal
+    /// SRAM that are sent to the panel. This stops the display drifting and flickering when Wi-Fi or flash
+    /// writes take the PSRAM bandwidth away from the LCD DMA.
+    #[must_use]
+    pub fn bounce_buffer(mut self, timings: &TimingsConfig) -> Self {
+        self.bounce_buffer_size_px = bounce_buffer_size_for(timings.horz_res, timings.vert_res);
+        self
+    }
+
+    #[must_use]
+    pub fn hsync_gpio_num(mut self, hsync_gpio_num: i32) -> Self {
+        self.hsync_gpio_num = hsync_gpio_num;
+        self
+    }
+
+    #[must_use]
+    pub fn vsync_gpio_num(mut self, vsync_gpio_num: i32) -> Self {
+        self.vsync_gpio_num = vsync_gpio_num;
+        self
+    }
+
+    #[must_use]
+    pub fn de_gpio_num(mut self, de_gpio_num: i32) -> Self {
+        self.de_gpio_num = de_gpio_num;
+        self
+    }
+
+    #[must_use]
+    pub fn pclk_gpio_num(mut self, pclk_gpio_num: i32) -> Self {
+        self.pclk_gpio_num = pclk_gpio_num;
+        self
+    }
+
+    #[must_use]
+    pub fn disp_gpio_num(mut self, disp_gpio_num: i32) -> Self {
+        self.disp_gpio_num = disp_gpio_num;
+        self
+    }
+
+    #[must_use]
+    pub fn data_gpio_nums(mut self, data_gpio_nums: [i32; 16]) -> Self {
+        self.data_gpio_nums = data_gpio_nums;
+        self
+    }
+}
+
+/// The bounce buffer size in pixels for a panel resolution.
+///
+/// ESP-IDF recommends bounce buffers of about 10 lines, the size is the largest number of lines up to 10 that
+/// makes the frame an even multiple of the bounce buffer size (required by the driver). When no number of whole
+/// lines does, the size is the largest that fits in 10 lines and divides the frame evenly, 0 (no bounce buffer)
+/// when the frame has an odd number of pixels.
+pub fn bounce_buffer_size_for(horz_res: u32, vert_res: u32) -> usize {
+    const MAX_LINES: usize = 10;
+
+    let (horz_res, vert_res) = (horz_res as usize, vert_res as usize);
+    if let Some(lines) = (1..=MAX_LINES)
+        .rev()
+        .find(|lines| vert_res % (2 * lines) == 0)
+    {
+        return horz_res * lines;
+    }
+
+    let frame_size_px = horz_res * vert_res;
+    (1..=horz_res * MAX_LINES)
+        .rev()
+        .find(|size| frame_size_px % (2 * size) == 0)
+        .unwrap_or(0)
+}
+
+impl Default for PanelConfig {
+    fn default() -> Self {
+        Self {
+            clk_src: soc_periph_lcd_clk_src_t_LCD_CLK_SRC_PLL160M,
+            data_width: 16,
+            bits_per_pixel: 0,
+            color_mode: ColorMode::Rgb565,
+            num_fbs: 1,
+            bounce_buffer_size_px: 0,
+            sram_trans_align: 8,
+            psram_trans_align: 64,
+            hsync_gpio_num: 39,
+            vsync_gpio_num: 40,
+            de_gpio_num: 41,
+            pclk_gpio_num: 42,
+            disp_gpio_num: -1,
+            data_gpio_nums: [15, 7, 6, 5, 4, 9, 46, 3, 8, 16, 1, 14, 21, 47, 48, 45],
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PanelFlagsConfig {
+    pub disp_active_low: u32,
+    pub refresh_on_demand: u32,
+    pub fb_in_psram: u32,
+    pub double_fb: u32,
+    pub no_fb: u32,
+    pub bb_invalidate_cache: u32,
+}
+
+impl PanelFlagsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn disp_active_low(mut self, enable: bool) -> Self {
+        self.disp_active_low = enable.into();
+        self
+    }
+
+    #[must_use]
+    pub fn refresh_on_demand(mut self, enable: bool) -> Self {
+        self.refresh_on_demand = enable.into();
+        self
+    }
+
+    #[must_use]
+    pub fn fb_in_psram(mut self, enable: bool) -> Self {
+        self.fb_in_psram = enable.into();
+        self
+    }
+
+    #[must_use]
+    pub fn double_fb(mut self, enable: bool) -> Self {
+        self.double_fb = enable.into();
+        self
+    }
+
+    #[must_use]
+    pub fn no_fb(mut self, enable: bool) -> Self {
+        self.no_fb = enable.into();
+        self
+    }
+
+    #[must_use]
+    pub fn bb_invalidate_cache(mut self, enable: bool) -> Self {
+        self.bb_invalidate_cache = enable.into();
+        self
+    }
+}
+
+impl Default for PanelFlagsConfig {
+    fn default() -> Self {
+        Self {
+            disp_active_low: 0,
+            refresh_on_demand: 0,
+            fb_in_psram: 1,
+            double_fb: 0,
+            no_fb: 0,
+            bb_invalidate_cache: 0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TimingsConfig {
+    pub pclk_hz: u32,
+    pub horz_res: u32,
+    pub vert_res: u32,
+    pub hsync_pulse_width: u32,
+    pub hsync_back_porch: u32,
+    pub hsync_front_porch: u32,
+    pub vsync_pulse_width: u32,
+    pub vsync_back_porch: u32,
+    pub vsync_front_porch: u32,
+}
+
+impl TimingsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn pclk_hz(mut self, hz: u32) -> Self {
+        self.pclk_hz = hz;
+        self
+    }
+
+    #[must_use]
+    pub fn horz_res(mut self, hres: u32) -> Self {
+        self.horz_res = hres;
+        self
+    }
+
+    #[must_use]
+    pub fn vert_res(mut self, vres: u32) -> Self {
+        self.vert_res = vres;
+        self
+    }
+
+    #[must_use]
+    pub fn hsync_pulse_width(mut self, hsync_pulse_width: u32) -> Self {
+        self.hsync_pulse_width = hsync_pulse_width;
+        self
+    }
+
+    #[must_use]
+    pub fn hsync_back_porch(mut self, hsync_back_porch: u32) -> Self {
+        self.hsync_back_porch = hsync_back_porch;
+        self
+    }
+
+    #[must_use]
+    pub fn hsync_front_porch(mut self, hsync_front_porch: u32) -> Self {
+        self.hsync_front_porch = hsync_front_porch;
+        self
+    }
+
+    #[must_use]
+    pub fn vsync_pulse_width(mut self, vsync_pulse_width: u32) -> Self {
+        self.vsync_pulse_width = vsync_pulse_width;
+        self
+    }
+
+    #[must_use]
+    pub fn vsync_back_porch(mut self, vsync_back_porch: u32) -> Self {
+        self.vsync_back_porch = vsync_back_porch;
+        self
+    }
+
+    #[must_use]
+    pub fn vsync_front_porch(mut self, vsync_front_porch: u32) -> Self {
+        self.vsync_front_porch = vsync_front_porch;
+        self
+    }
+}
+
+impl Default for TimingsConfig {
+    fn default() -> Self {
+        Self {
+            pclk_hz: (16 * 1000 * 1000),
+            horz_res: 800,
+            vert_res: 480,
+            hsync_pulse_width: 30,
+            hsync_back_porch: 16,
+            hsync_front_porch: 210,
+            vsync_pulse_width: 13,
+            vsync_back_porch: 10,
+            vsync_front_porch: 22,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TimingFlagsConfig {
+    pub hsync_idle_low: u32,
+    pub vsync_idle_low: u32,
+    pub de_idle_high: u32,
+    pub pclk_active_neg: u32,
+    pub pclk_idle_high: u32,
+}
+
+impl TimingFlagsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn hsync_idle_low(mut self, enable: bool) -> Self {
+        self.hsync_idle_low = enable.into();
+        self
+    }
+
+    #[must_use]
+    pub fn vsync_idle_low(mut self, enable: bool) -> Self {
+        self.vsync_idle_low = enable.into();
+        self
+    }
+
+    #[must_use]
+    pub fn de_idle_high(mut self, enable: bool) -> Self {
+        self.de_idle_high = enable.into();
+        self
+    }
+
+    #[must_use]
+    pub fn pclk_active_neg(mut self, enable: bool) -> Self {
+        self.pclk_active_neg = enable.into();
+        self
+    }
+
+    #[must_use]
+    pub fn set_pclk_idle_high(mut self, enable: bool) -> Self {
+        self.pclk_idle_high = enable.into();
+        self
+    }
+}
+
+impl Default for TimingFlagsConfig {
+    fn default() -> Self {
+        Self {
+            hsync_idle_low: 0,
+            vsync_idle_low: 0,
+            de_idle_high: 0,
+            pclk_active_neg: 1,
+            pclk_idle_high: 0,
+        }
+    }
+}
+
+/// Maximum number of frame buffers supported by the RGB LCD driver
+const MAX_NUM_FBS: usize = 3;
+
+/// Frame buffers larger than this can not be allocated in internal SRAM
+const MAX_SRAM_FB_BYTES: usize = 256 * 1024;
+
+const DATA_SIGNALS: [&str; 16] = [
+    "data0", "data1", "data2", "data3", "data4", "data5", "data6", "data7", "data8", "data9",
+    "data10", "data11", "data12", "data13", "data14", "data15",
+];
+
+/// Errors found by `PanelConfig::validate`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PanelConfigError {
+    InvalidDataWidth(usize),
+    InvalidBitsPerPixel {
+        bits_per_pixel: usize,
+        data_width: usize,
+    },
+    ColorModeMismatch {
+        color_mode: ColorMode,
+        bits_per_pixel: usize,
+    },
+    Rgb888DataWidth(usize),
+    MissingGpio(&'static str),
+    InvalidGpio {
+        signal: &'static str,
+        gpio: i32,
+    },
+    ReservedGpio {
+        signal: &'static str,
+        gpio: i32,
+    },
+    DuplicateGpio {
+        gpio: i32,
+        first: &'static str,
+        second: &'static str,
+    },
+    TooManyFrameBuffers(usize),
+    DoubleFbMismatch(usize),
+    NoFbMismatch(usize),
+    NoFbAndDoubleFb,
+    NoFbWithoutBounceBuffer,
+    /// Nothing fills the bounce buffers from Lvgl yet so the no_fb flag can not be used
+    NoFbNotSupported,
+    RefreshOnDemandWithBounceBuffer,
+    InvalidBounceBufferSize {
+        bounce_buffer_size_px: usize,
+        frame_size_px: usize,
+    },
+    InvalidSramAlignment(usize),
+    InvalidPsramAlignment(usize),
+    FrameBufferTooLargeForSram(usize),
+    InvalidResolution {
+        horz_res: u32,
+        vert_res: u32,
+    },
+    InvalidPclk,
+}
+
+impl fmt::Display for PanelConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PanelConfigError::InvalidDataWidth(width) => {
+                write!(f, "data_width is {}, the RGB panel supports a data width of 8 or 16", width)
+            }
+            PanelConfigError::InvalidBitsPerPixel {
+                bits_per_pixel,
+                data_width,
+            } => write!(
+                f,
+                "bits_per_pixel is {}, it must be 0 (same as data_width) or a multiple of 8 that is at least the \
+                 data_width of {}",
+                bits_per_pixel, data_width
+            ),
+            PanelConfigError::ColorModeMismatch {
+                color_mode,
+                bits_per_pixel,
+            } => write!(
+                f,
+                "the color mode {:?} has {} bits per pixel but bits_per_pixel is {}, set the color mode with \
+                 PanelConfig::color_mode",
+                color_mode,
+                color_mode.bits_per_pixel(),
+                bits_per_pixel
+            ),
+            PanelConfigError::Rgb888DataWidth(width) => write!(
+                f,
+                "data_width is {}, RGB888 is sent over an 8 bit bus. The ESP32S3 has at most 16 data lines, \
+                 drive a 24 bit parallel panel as RGB565 with the low bits of each color tied",
+                width
+            ),
+            PanelConfigError::MissingGpio(signal) => write!(f, "no GPIO is set for {}", signal),
+            PanelConfigError::InvalidGpio { signal, gpio } => {
+                write!(f, "GPIO {} used for {} does not exist on the ESP32S3", gpio, signal)
+            }
+            PanelConfigError::ReservedGpio { signal, gpio } => write!(
+                f,
+                "GPIO {} used for {} is reserved for the SPI flash or the octal PSRAM",
+                gpio, signal
+            ),
+            PanelConfigError::DuplicateGpio { gpio, first, second } => {
+                write!(f, "GPIO {} is used for both {} and {}", gpio, first, second)
+            }
+            PanelConfigError::TooManyFrameBuffers(num_fbs) => write!(
+                f,
+                "num_fbs is {}, at most {} frame buffers are supported",
+                num_fbs, MAX_NUM_FBS
+            ),
+            PanelConfigError::DoubleFbMismatch(num_fbs) => write!(
+                f,
+                "double_fb is set but num_fbs is {}, set num_fbs to 2 (or 0) when using double_fb",
+                num_fbs
+            ),
+            PanelConfigError::NoFbMismatch(num_fbs) => write!(
+                f,
+                "no_fb is set but num_fbs is {}, set num_fbs to 0 when using no_fb",
+                num_fbs
+            ),
+            PanelConfigError::NoFbAndDoubleFb => write!(f, "no_fb and double_fb can not both be set"),
+            PanelConfigError::NoFbWithoutBounceBuffer => write!(
+                f,
+                "no_fb is set but bounce_buffer_size_px is 0, without a frame buffer a bounce buffer is required"
+            ),
+            PanelConfigError::NoFbNotSupported => write!(
+                f,
+                "no_fb is not supported, Lvgl is flushed to the frame buffer and there is no bounce buffer fill callback"
+            ),
+            PanelConfigError::RefreshOnDemandWithBounceBuffer => write!(
+                f,
+                "refresh_on_demand is set, bounce buffers can only be used with a streamed panel"
+            ),
+            PanelConfigError::InvalidBounceBufferSize {
+                bounce_buffer_size_px,
+                frame_size_px,
+            } => write!(
+                f,
+                "bounce_buffer_size_px is {}, the frame size of {} pixels must be an even multiple of it",
+                bounce_buffer_size_px, frame_size_px
+            ),
+            PanelConfigError::InvalidSramAlignment(align) => {
+                write!(f, "sram_trans_align is {}, it must be a power of 2", align)
+            }
+            PanelConfigError::InvalidPsramAlignment(align) => write!(
+                f,
+                "psram_trans_align is {}, frame buffers in PSRAM must be aligned to 16, 32 or 64 bytes",
+                align
+            ),
+            PanelConfigError::FrameBufferTooLargeForSram(bytes) => write!(
+                f,
+                "the frame buffers need {} bytes which does not fit in internal SRAM, set fb_in_psram",
+                bytes
+            ),
+            PanelConfigError::InvalidResolution { horz_res, vert_res } => {
+                write!(f, "the resolution {}x{} is not valid", horz_res, vert_res)
+            }
+            PanelConfigError::InvalidPclk => write!(f, "pclk_hz must not be 0"),
+        }
+    }
+}
+
+impl std::error::Error for PanelConfigError {}
+
+/// The panel timing computed by `PanelConfig::validate`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PanelTiming {
+    /// Frames per second sent to the panel
+    pub refresh_rate_hz: f32,
+    /// Size of one frame buffer in bytes
+    pub frame_size_bytes: usize,
+    /// Number of frame buffers the driver will allocate
+    pub num_fbs: usize,
+}
+
+impl TimingsConfig {
+    /// Pixel clocks per line including the hsync pulse and porches
+    pub fn horz_total(&self) -> u32 {
+        self.horz_res + self.hsync_pulse_width + self.hsync_back_porch + self.hsync_front_porch
+    }
+
+    /// Lines per frame including the vsync pulse and porches
+    pub fn vert_total(&self) -> u32 {
+        self.vert_res + self.vsync_pulse_width + self.vsync_back_porch + self.vsync_front_porch
+    }
+
+    /// Frames per second sent to the panel
+    pub fn refresh_rate_hz(&self) -> f32 {
+        let pixels_per_frame = self.horz_total() as f32 * self.vert_total() as f32;
+        if pixels_per_frame == 0.0 {
+            0.0
+        } else {
+            self.pclk_hz as f32 / pixels_per_frame
+        }
+    }
+}
+
+impl PanelConfig {
+    /// The number of frame buffers the driver allocates, the double_fb and no_fb flags override num_fbs
+    pub fn frame_buffer_count(&self, flags: &PanelFlagsConfig) -> usize {
+        if flags.no_fb != 0 {
+            0
+        } else if flags.double_fb != 0 {
+            2
+        } else {
+            self.num_fbs.max(1)
+        }
+    }
+
+    /// Checks the panel configuration before it is passed to ESP-IDF and computes the resulting timing.
+    pub fn validate(
+        &self,
+        flags: &PanelFlagsConfig,
+        timings: &TimingsConfig,
+    ) -> Result<PanelTiming, PanelConfigError> {
+        if self.data_width != 8 && self.data_width != 16 {
+            return Err(PanelConfigError::InvalidDataWidth(self.data_width));
+        }
+
+        let bits_per_pixel = if self.bits_per_pixel == 0 {
+            self.data_width
+        } else {
+            self.bits_per_pixel
+        };
+        if bits_per_pixel % 8 != 0 || bits_per_pixel < self.data_width {
+            return Err(PanelConfigError::InvalidBitsPerPixel {
+                bits_per_pixel: self.bits_per_pixel,
+                data_width: self.data_width,
+            });
+        }
+
+        if bits_per_pixel != self.color_mode.bits_per_pixel() {
+            return Err(PanelConfigError::ColorModeMismatch {
+                color_mode: self.color_mode,
+                bits_per_pixel,
+            });
+        }
+        if self.color_mode == ColorMode::Rgb888 && self.data_width != 8 {
+            return Err(PanelConfigError::Rgb888DataWidth(self.data_width));
+        }
+
+        self.validate_gpios()?;
+
+        if timings.horz_res == 0 || timings.vert_res == 0 {
+            return Err(PanelConfigError::InvalidResolution {
+                horz_res: timings.horz_res,
+                vert_res: timings.vert_res,
+            });
+        }
+        if timings.pclk_hz == 0 {
+            return Err(PanelConfigError::InvalidPclk);
+        }
+
+        // Number of frame buffers, the flags override num_fbs
+        let num_fbs = match (flags.no_fb != 0, flags.double_fb != 0) {
+            (true, true) => return Err(PanelConfigError::NoFbAndDoubleFb),
+            (true, false) => {
+                if self.num_fbs != 0 {
+                    return Err(PanelConfigError::NoFbMismatch(self.num_fbs));
+                }
+                if self.bounce_buffer_size_px == 0 {
+                    return Err(PanelConfigError::NoFbWithoutBounceBuffer);
+                }
+                // The Lvgl flush draws into the frame buffer with esp_lcd_panel_draw_bitmap, without a frame
+                // buffer the bounce buffers would have to be filled from an on_bounce_empty callback instead
+                return Err(PanelConfigError::NoFbNotSupported);
+            }
+            (false, true) => {
+                if self.num_fbs != 0 && self.num_fbs != 2 {
+                    return Err(PanelConfigError::DoubleFbMismatch(self.num_fbs));
+                }
+                2
+            }
+            (false, false) => {
+                if self.num_fbs > MAX_NUM_FBS {
+                    return Err(PanelConfigError::TooManyFrameBuffers(self.num_fbs));
+                }
+                self.num_fbs.max(1)
+            }
+        };
+
+        if flags.refresh_on_demand != 0 && self.bounce_buffer_size_px != 0 {
+            return Err(PanelConfigError::RefreshOnDemandWithBounceBuffer);
+        }
+
+        let frame_size_px = (timings.horz_res * timings.vert_res) as usize;
+        if self.bounce_buffer_size_px != 0 && frame_size_px % (2 * self.bounce_buffer_size_px) != 0
+        {
+            return Err(PanelConfigError::InvalidBounceBufferSize {
+                bounce_buffer_size_px: self.bounce_buffer_size_px,
+                frame_size_px,
+            });
+        }
+
+        if !self.sram_trans_align.is_power_of_two() {
+            return Err(PanelConfigError::InvalidSramAlignment(
+                self.sram_trans_align,
+            ));
+        }
+
+        let frame_size_bytes = frame_size_px * bits_per_pixel / 8;
+        if flags.fb_in_psram != 0 {
+            if !matches!(self.psram_trans_align, 16 | 32 | 64) {
+                return Err(PanelConfigError::InvalidPsramAlignment(
+                    self.psram_trans_align,
+                ));
+            }
+        } else if frame_size_bytes * num_fbs > MAX_SRAM_FB_BYTES {
+            return Err(PanelConfigError::FrameBufferTooLargeForSram(
+                frame_size_bytes * num_fbs,
+            ));
+        }
+
+        Ok(PanelTiming {
+            refresh_rate_hz: timings.refresh_rate_hz(),
+            frame_size_bytes,
+            num_fbs,
+        })
+    }
+
+    // Check the GPIOs exist, are not reserved and are only used once
+    fn validate_gpios(&self) -> Result<(), PanelConfigError> {
+        if self.pclk_gpio_num < 0 {
+            return Err(PanelConfigError::MissingGpio("pclk"));
+        }
+
+        let mut used: Vec<(&'static str, i32)> = vec![
+            ("hsync", self.hsync_gpio_num),
+            ("vsync", self.vsync_gpio_num),
+            ("de", self.de_gpio_num),
+            ("pclk", self.pclk_gpio_num),
+            ("disp", self.disp_gpio_num),
+        ];
+        for (index, gpio) in self.data_gpio_nums[..self.data_width].iter().enumerate() {
+            if *gpio < 0 {
+                return Err(PanelConfigError::MissingGpio(DATA_SIGNALS[index]));
+            }
+            used.push((DATA_SIGNALS[index], *gpio));
+        }
+
+        // -1 means the signal is not used
+        used.retain(|(_, gpio)| *gpio >= 0);
+
+        for (index, &(signal, gpio)) in used.iter().enumerate() {
+            // GPIO 22 - 25 do not exist on the ESP32S3
+            if gpio > 48 || (22..=25).contains(&gpio) {
+                return Err(PanelConfigError::InvalidGpio { signal, gpio });
+            }
+
+            // GPIO 26 - 32 are used by the SPI flash and PSRAM, GPIO 33 - 37 are also used by octal PSRAM
+            if (26..=37).contains(&gpio) {
+                return Err(PanelConfigError::ReservedGpio { signal, gpio });
+            }
+
+            if let Some(&(first, _)) = used[..index].iter().find(|(_, g)| *g == gpio) {
+                return Err(PanelConfigError::DuplicateGpio {
+                    gpio,
+                    first,
+                    second: signal,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(config: &PanelConfig) -> Result<PanelTiming, PanelConfigError> {
+        config.validate(&PanelFlagsConfig::new(), &TimingsConfig::new())
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        let timing = validate(&PanelConfig::new()).unwrap();
+        assert_eq!(timing.frame_size_bytes, 800 * 480 * 2);
+        assert_eq!(timing.num_fbs, 1);
+    }
+
+    #[test]
+    fn refresh_rate() {
+        let timings = TimingsConfig::new();
+        assert_eq!(timings.horz_total(), 1056);
+        assert_eq!(timings.vert_total(), 525);
+        assert!((timings.refresh_rate_hz() - 28.86).abs() < 0.01);
+
+        let timing = validate(&PanelConfig::new()).unwrap();
+        assert_eq!(timing.refresh_rate_hz, timings.refresh_rate_hz());
+
+        let timings = TimingsConfig {
+            horz_res: 0,
+            hsync_pulse_width: 0,
+            hsync_back_porch: 0,
+            hsync_front_porch: 0,
+            ..TimingsConfig::new()
+        };
+        assert_eq!(timings.refresh_rate_hz(), 0.0);
+    }
+
+    #[test]
+    fn bounce_buffer_sizes() {
+        assert_eq!(bounce_buffer_size_for(800, 480), 800 * 10);
+        assert_eq!(bounce_buffer_size_for(480, 272), 480 * 8);
+        // No whole number of lines divides the frame
+        assert_eq!(bounce_buffer_size_for(480, 271), 271 * 16);
+        assert_eq!(bounce_buffer_size_for(481, 271), 0);
+
+        for (horz_res, vert_res) in [(800, 480), (480, 272), (480, 271), (1024, 600), (720, 719)] {
+            let timings = TimingsConfig::new().horz_res(horz_res).vert_res(vert_res);
+            assert!(PanelConfig::new()
+                .bounce_buffer(&timings)
+                .validate(&PanelFlagsConfig::new(), &timings)
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn data_width_and_bits_per_pixel() {
+        assert_eq!(
+            validate(&PanelConfig::new().data_width(12)),
+            Err(PanelConfigError::InvalidDataWidth(12))
+        );
+        assert_eq!(
+            validate(&PanelConfig::new().bits_per_pixel(12)),
+            Err(PanelConfigError::InvalidBitsPerPixel {
+                bits_per_pixel: 12,
+                data_width: 16
+            })
+        );
+        assert_eq!(
+            validate(&PanelConfig::new().bits_per_pixel(24)),
+            Err(PanelConfigError::ColorModeMismatch {
+                color_mode: ColorMode::Rgb565,
+                bits_per_pixel: 24
+            })
+        );
+        assert_eq!(
+            validate(&PanelConfig::new().color_mode(ColorMode::Rgb888)),
+            Err(PanelConfigError::Rgb888DataWidth(16))
+        );
+        assert!(validate(
+            &PanelConfig::new()
+                .color_mode(ColorMode::Rgb888)
+                .data_width(8)
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn lvgl_color_supported() {
+        for color_mode in [
+            ColorMode::Rgb565,
+            ColorMode::Rgb565Swapped,
+            ColorMode::Rgb888,
+        ] {
+            assert!(color_mode.lvgl_color_supported(16, 0), "{:?}", color_mode);
+            // The flush swaps the bytes itself and does not convert from the other Lvgl color depths
+            assert!(!color_mode.lvgl_color_supported(16, 1), "{:?}", color_mode);
+            for color_depth in [1, 8, 32] {
+                assert!(!color_mode.lvgl_color_supported(color_depth, 0));
+            }
+        }
+    }
+
+    #[test]
+    fn gpios() {
+        assert_eq!(
+            validate(&PanelConfig::new().pclk_gpio_num(-1)),
+            Err(PanelConfigError::MissingGpio("pclk"))
+        );
+
+        let mut data_gpio_nums = PanelConfig::new().data_gpio_nums;
+        data_gpio_nums[3] = -1;
+        assert_eq!(
+            validate(&PanelConfig::new().data_gpio_nums(data_gpio_nums)),
+            Err(PanelConfigError::MissingGpio("data3"))
+        );
+
+        assert_eq!(
+            validate(&PanelConfig::new().hsync_gpio_num(23)),
+            Err(PanelConfigError::InvalidGpio {
+                signal: "hsync",
+                gpio: 23
+            })
+        );
+        assert_eq!(
+            validate(&PanelConfig::new().vsync_gpio_num(33)),
+            Err(PanelConfigError::ReservedGpio {
+                signal: "vsync",
+                gpio: 33
+            })
+        );
+        assert_eq!(
+            validate(&PanelConfig::new().de_gpio_num(39)),
+            Err(PanelConfigError::DuplicateGpio {
+                gpio: 39,
+                first: "hsync",
+                second: "de"
+            })
+        );
+
+        // Only the data lines of the data width are used, RGB565 over 8 lines takes two clocks a pixel
+        let mut data_gpio_nums = PanelConfig::new().data_gpio_nums;
+        data_gpio_nums[8..].fill(-1);
+        assert!(validate(
+            &PanelConfig::new()
+                .data_width(8)
+                .bits_per_pixel(16)
+                .data_gpio_nums(data_gpio_nums)
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn frame_buffers() {
+        let config = PanelConfig::new();
+        let timings = TimingsConfig::new();
+        let flags = PanelFlagsConfig::new();
+
+        assert_eq!(
+            config.num_fbs(4).validate(&flags, &timings),
+            Err(PanelConfigError::TooManyFrameBuffers(4))
+        );
+        assert_eq!(
+            config.num_fbs(3).validate(&flags.double_fb(true), &timings),
+            Err(PanelConfigError::DoubleFbMismatch(3))
+        );
+        assert_eq!(
+            config
+                .num_fbs(2)
+                .validate(&flags.double_fb(true), &timings)
+                .map(|t| t.num_fbs),
+            Ok(2)
+        );
+        assert_eq!(
+            config.validate(&flags.no_fb(true), &timings),
+            Err(PanelConfigError::NoFbMismatch(1))
+        );
+        assert_eq!(
+            config.num_fbs(0).validate(&flags.no_fb(true), &timings),
+            Err(PanelConfigError::NoFbWithoutBounceBuffer)
+        );
+        assert_eq!(
+            config
+                .num_fbs(0)
+                .bounce_buffer_size_px(8000)
+                .validate(&flags.no_fb(true), &timings),
+            Err(PanelConfigError::NoFbNotSupported)
+        );
+        assert_eq!(
+            config
+                .bounce_buffer_size_px(8000)
+                .validate(&flags.refresh_on_demand(true), &timings),
+            Err(PanelConfigError::RefreshOnDemandWithBounceBuffer)
+        );
+        assert_eq!(
+            config.validate(&flags.no_fb(true).double_fb(true), &timings),
+            Err(PanelConfigError::NoFbAndDoubleFb)
+        );
+        assert_eq!(
+            config.validate(&flags.fb_in_psram(false), &timings),
+            Err(PanelConfigError::FrameBufferTooLargeForSram(800 * 480 * 2))
+        );
+    }
+
+    #[test]
+    fn alignment_resolution_and_clock() {
+        assert_eq!(
+            validate(&PanelConfig::new().sram_trans_align(12)),
+            Err(PanelConfigError::InvalidSramAlignment(12))
+        );
+        assert_eq!(
+            validate(&PanelConfig::new().psram_trans_align(8)),
+            Err(PanelConfigError::InvalidPsramAlignment(8))
+        );
+        assert_eq!(
+            validate(&PanelConfig::new().bounce_buffer_size_px(7000)),
+            Err(PanelConfigError::InvalidBounceBufferSize {
+                bounce_buffer_size_px: 7000,
+                frame_size_px: 800 * 480
+            })
+        );
+
+        let flags = PanelFlagsConfig::new();
+        assert_eq!(
+            PanelConfig::new().validate(&flags, &TimingsConfig::new().vert_res(0)),
+            Err(PanelConfigError::InvalidResolution {
+                horz_res: 800,
+                vert_res: 0
+            })
+        );
+        assert_eq!(
+            PanelConfig::new().validate(&flags, &TimingsConfig::new().pclk_hz(0)),
+            Err(PanelConfigError::InvalidPclk)
+        );
+    }
+}
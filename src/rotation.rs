@@ -0,0 +1,121 @@
+//! Software rotation of the display.
+//!
+//! The RGB panel is always scanned in its native (landscape) orientation. With a rotation Lvgl draws in the
+//! rotated coordinates and every flushed region is mapped onto the panel and its pixels transposed before they
+//! are copied into the frame buffer. The rotations match the GT911 orientations (see `Rotation::orientation`)
+//! so the touch points stay in sync with the display from a single setting.
+use crate::gt911::Orientation;
+
+/// Display rotation relative to the native panel orientation
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Rotation {
+    /// Native orientation, no rotation
+    #[default]
+    Deg0,
+    /// Rotated 90 degrees counter-clockwise, width and height are swapped
+    Deg90,
+    /// Rotated 180 degrees
+    Deg180,
+    /// Rotated 90 degrees clockwise (270 degrees counter-clockwise), width and height are swapped
+    Deg270,
+}
+
+impl Rotation {
+    /// Returns None if `degrees` is not 0, 90, 180 or 270
+    pub fn from_degrees(degrees: u16) -> Option<Self> {
+        match degrees {
+            0 => Some(Rotation::Deg0),
+            90 => Some(Rotation::Deg90),
+            180 => Some(Rotation::Deg180),
+            270 => Some(Rotation::Deg270),
+            _ => None,
+        }
+    }
+
+    pub fn degrees(&self) -> u16 {
+        match self {
+            Rotation::Deg0 => 0,
+            Rotation::Deg90 => 90,
+            Rotation::Deg180 => 180,
+            Rotation::Deg270 => 270,
+        }
+    }
+
+    /// The GT911 orientation that maps the touch points onto the rotated display
+    pub fn orientation(&self) -> Orientation {
+        match self {
+            Rotation::Deg0 => Orientation::Landscape,
+            Rotation::Deg90 => Orientation::Portrait,
+            Rotation::Deg180 => Orientation::InvertedLandscape,
+            Rotation::Deg270 => Orientation::InvertedPortrait,
+        }
+    }
+
+    /// Returns true if the rotation swaps the width and height of the panel
+    pub fn swaps_axes(&self) -> bool {
+        matches!(self, Rotation::Deg90 | Rotation::Deg270)
+    }
+
+    /// The width and height of the rotated display for a panel of `width` x `height` pixels
+    pub fn display_size(&self, width: u32, height: u32) -> (u32, u32) {
+        if self.swaps_axes() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// Maps a region of the rotated display onto the panel.
+    ///
+    /// The region is `sx..ex` by `sy..ey` (the end coordinates are exclusive) and `width` x `height` is the size
+    /// of the panel. Returns the region on the panel as `(sx, sy, ex, ey)`.
+    pub fn panel_region(
+        &self,
+        sx: i32,
+        sy: i32,
+        ex: i32,
+        ey: i32,
+        width: i32,
+        height: i32,
+    ) -> (i32, i32, i32, i32) {
+        match self {
+            Rotation::Deg0 => (sx, sy, ex, ey),
+            Rotation::Deg90 => (width - ey, sx, width - sy, ex),
+            Rotation::Deg180 => (width - ex, height - ey, width - sx, height - sy),
+            Rotation::Deg270 => (sy, height - ex, ey, height - sx),
+        }
+    }
+
+    /// Copies the pixels of a `width` x `height` region of the rotated display into `dst` in panel order ie
+    /// the pixels of the region returned by `panel_region`, row first.
+    ///
+    /// Only `width * height` pixels are copied, `src` and `dst` must hold at least that many pixels.
+    pub fn rotate_pixels<T: Copy>(&self, src: &[T], width: usize, height: usize, dst: &mut [T]) {
+        let len = width * height;
+        let (src, dst) = (&src[..len], &mut dst[..len]);
+
+        match self {
+            Rotation::Deg0 => dst.copy_from_slice(src),
+            Rotation::Deg180 => {
+                for (d, s) in dst.iter_mut().zip(src.iter().rev()) {
+                    *d = *s;
+                }
+            }
+            // The panel region is `height` pixels wide
+            Rotation::Deg90 => {
+                for (row, line) in src.chunks_exact(width).enumerate() {
+                    for (col, pixel) in line.iter().enumerate() {
+                        dst[col * height + (height - 1 - row)] = *pixel;
+                    }
+                }
+            }
+            Rotation::Deg270 => {
+                for (row, line) in src.chunks_exact(width).enumerate() {
+                    for (col, pixel) in line.iter().enumerate() {
+                        dst[(width - 1 - col) * height + row] = *pixel;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,482 @@
+//! Persistent device settings.
+//!
+//! The settings are stored as a single blob that starts with the schema version. When the schema changes the
+//! version is bumped and a migration is added to `MIGRATIONS` that converts a blob of the previous version, so
+//! settings saved by an older firmware are upgraded when they are loaded. Settings saved by a newer firmware
+//! (a downgrade) are not loaded.
+//!
+//! The blob is stored with a `SettingsStorage`, on the ESP32S3 this is NVS and `MemoryStorage` keeps the
+//! settings in memory for the simulator and tests.
+use core::fmt;
+use core::time::Duration;
+use std::collections::HashMap;
+
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::nvs::{EspNvs, NvsPartitionId};
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::sys::EspError;
+
+use crate::calibration::{AffineTransform, AFFINE_TRANSFORM_LEN};
+use crate::rotation::Rotation;
+
+/// Storage key of the settings blob
+const SETTINGS_KEY: &str = "settings";
+
+/// The current schema version
+pub const SCHEMA_VERSION: u8 = 2;
+
+/// Maximum size in bytes of the settings blob
+const MAX_SETTINGS_LEN: usize = 64;
+
+/// Converts a settings blob of version `index + 1` into a blob of version `index + 2` (without the version byte)
+type Migration = fn(&[u8]) -> Option<Vec<u8>>;
+
+/// The migrations from each schema version to the next
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize - 1] = [drop_theme_and_locale];
+
+// Length of a version 2 blob (without the version byte)
+const V2_LEN: usize = 3 + AFFINE_TRANSFORM_LEN + 4;
+
+// Version 1 also stored a theme and a locale, nothing applied them so version 2 leaves them out
+fn drop_theme_and_locale(data: &[u8]) -> Option<Vec<u8>> {
+    let mut reader = Reader { data };
+    let kept = reader.bytes(V2_LEN)?;
+    let _theme = reader.u8()?;
+    let locale_len = reader.u8()? as usize;
+    reader.bytes(locale_len)?;
+    Some(kept.to_vec())
+}
+
+/// Key value storage for the settings
+pub trait SettingsStorage {
+    type Error;
+
+    /// Reads the value of `key` into `buf`, returns None if `key` is not stored
+    fn read<'a>(&self, key: &str, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, Self::Error>;
+
+    fn write(&mut self, key: &str, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Returns true if `key` was stored
+    fn remove(&mut self, key: &str) -> Result<bool, Self::Error>;
+}
+
+#[cfg(target_os = "espidf")]
+impl<T: NvsPartitionId> SettingsStorage for EspNvs<T> {
+    type Error = EspError;
+
+    fn read<'a>(&self, key: &str, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, Self::Error> {
+        self.get_blob(key, buf)
+    }
+
+    fn write(&mut self, key: &str, data: &[u8]) -> Result<(), Self::Error> {
+        self.set_blob(key, data)
+    }
+
+    fn remove(&mut self, key: &str) -> Result<bool, Self::Error> {
+        EspNvs::remove(self, key)
+    }
+}
+
+/// Error returned by `MemoryStorage` when the value does not fit in the read buffer
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BufferTooSmall;
+
+impl fmt::Display for BufferTooSmall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "buffer too small")
+    }
+}
+
+impl std::error::Error for BufferTooSmall {}
+
+/// Settings storage in memory, the settings are lost when it is dropped
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStorage {
+    values: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SettingsStorage for MemoryStorage {
+    type Error = BufferTooSmall;
+
+    fn read<'a>(&self, key: &str, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, Self::Error> {
+        match self.values.get(key) {
+            Some(value) => {
+                let buf = buf.get_mut(..value.len()).ok_or(BufferTooSmall)?;
+                buf.copy_from_slice(value);
+                Ok(Some(buf))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn write(&mut self, key: &str, data: &[u8]) -> Result<(), Self::Error> {
+        self.values.insert(key.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> Result<bool, Self::Error> {
+        Ok(self.values.remove(key).is_some())
+    }
+}
+
+/// The device settings
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    /// Backlight brightness (0 - 100%)
+    pub brightness: u8,
+    pub rotation: Rotation,
+    /// The touch calibration, only valid for the rotation it was made with (see `set_rotation`)
+    pub touch_calibration: Option<AffineTransform>,
+    /// Time without a touch before the backlight turns off, None to never turn off
+    pub screen_timeout: Option<Duration>,
+}
+
+impl Settings {
+    /// Sets the rotation, the touch calibration is cleared when the rotation changes since it was made for the
+    /// previous rotation.
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        if rotation != self.rotation {
+            self.rotation = rotation;
+            self.touch_calibration = None;
+        }
+    }
+
+    /// Serializes the settings, the first byte is the schema version
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MAX_SETTINGS_LEN);
+        bytes.push(SCHEMA_VERSION);
+        bytes.push(self.brightness.min(100));
+        bytes.push((self.rotation.degrees() / 90) as u8);
+
+        match &self.touch_calibration {
+            Some(calibration) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&calibration.to_bytes());
+            }
+            None => {
+                bytes.push(0);
+                bytes.extend_from_slice(&[0; AFFINE_TRANSFORM_LEN]);
+            }
+        }
+
+        // 0 is never
+        let timeout_secs = self.screen_timeout.map_or(0, |timeout| {
+            timeout.as_secs().clamp(1, u32::MAX as u64) as u32
+        });
+        bytes.extend_from_slice(&timeout_secs.to_le_bytes());
+
+        bytes
+    }
+
+    /// Deserializes settings written by `to_bytes`, settings of an older schema version are migrated
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let (&version, data) = bytes.split_first().ok_or(DecodeError::Invalid)?;
+        if version == 0 || version > SCHEMA_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        let mut data = data.to_vec();
+        for migration in &MIGRATIONS[version as usize - 1..] {
+            data = migration(&data).ok_or(DecodeError::Invalid)?;
+        }
+
+        Self::decode(&data).ok_or(DecodeError::Invalid)
+    }
+
+    // Decode the current schema version (without the version byte)
+    fn decode(data: &[u8]) -> Option<Self> {
+        let mut reader = Reader { data };
+
+        let brightness = reader.u8()?.min(100);
+        let rotation = Rotation::from_degrees(reader.u8()? as u16 * 90)?;
+
+        let has_calibration = reader.u8()? != 0;
+        let calibration = AffineTransform::from_bytes(reader.bytes(AFFINE_TRANSFORM_LEN)?);
+        let touch_calibration = if has_calibration {
+            Some(calibration?)
+        } else {
+            None
+        };
+
+        let timeout_secs = u32::from_le_bytes(reader.bytes(4)?.try_into().ok()?);
+        let screen_timeout = (timeout_secs != 0).then(|| Duration::from_secs(timeout_secs as u64));
+
+        Some(Self {
+            brightness,
+            rotation,
+            touch_calibration,
+            screen_timeout,
+        })
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            brightness: 100,
+            rotation: Rotation::Deg0,
+            touch_calibration: None,
+            screen_timeout: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+// Reads the fields of a settings blob
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|bytes| bytes[0])
+    }
+}
+
+/// Errors returned when decoding a settings blob
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DecodeError {
+    /// The settings were saved with a schema version this firmware does not know
+    UnsupportedVersion(u8),
+    /// The settings blob is not valid
+    Invalid,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnsupportedVersion(version) => write!(
+                f,
+                "settings schema version {} is not supported, the latest version is {}",
+                version, SCHEMA_VERSION
+            ),
+            DecodeError::Invalid => write!(f, "stored settings are not valid"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Errors returned when loading or saving the settings
+#[derive(Debug)]
+pub enum SettingsError<E> {
+    Storage(E),
+    Decode(DecodeError),
+}
+
+impl<E> From<DecodeError> for SettingsError<E> {
+    fn from(error: DecodeError) -> Self {
+        SettingsError::Decode(error)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for SettingsError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::Storage(e) => write!(f, "settings storage error: {}", e),
+            SettingsError::Decode(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for SettingsError<E> {}
+
+/// Loads and saves the settings
+pub struct SettingsStore<S: SettingsStorage> {
+    storage: S,
+}
+
+impl<S: SettingsStorage> SettingsStore<S> {
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+
+    /// Loads the settings, returns None if no settings have been saved
+    pub fn load(&self) -> Result<Option<Settings>, SettingsError<S::Error>> {
+        let mut buf = [0u8; MAX_SETTINGS_LEN];
+        match self
+            .storage
+            .read(SETTINGS_KEY, &mut buf)
+            .map_err(SettingsError::Storage)?
+        {
+            Some(bytes) => Ok(Some(Settings::from_bytes(bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save(&mut self, settings: &Settings) -> Result<(), SettingsError<S::Error>> {
+        self.storage
+            .write(SETTINGS_KEY, &settings.to_bytes())
+            .map_err(SettingsError::Storage)
+    }
+
+    /// Removes the saved settings so the defaults are used
+    pub fn reset(&mut self) -> Result<(), SettingsError<S::Error>> {
+        self.storage
+            .remove(SETTINGS_KEY)
+            .map(|_| ())
+            .map_err(SettingsError::Storage)
+    }
+
+    pub fn into_inner(self) -> S {
+        self.storage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calibration() -> AffineTransform {
+        AffineTransform {
+            a: 1.0,
+            b: 0.5,
+            c: 3.0,
+            d: 0.0,
+            e: 1.0,
+            f: -2.0,
+        }
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            brightness: 42,
+            rotation: Rotation::Deg180,
+            touch_calibration: Some(calibration()),
+            screen_timeout: Some(Duration::from_secs(90)),
+        }
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let settings = settings();
+        let bytes = settings.to_bytes();
+        assert_eq!(bytes[0], SCHEMA_VERSION);
+        assert!(bytes.len() <= MAX_SETTINGS_LEN);
+        assert_eq!(Settings::from_bytes(&bytes), Ok(settings));
+
+        let never = Settings {
+            touch_calibration: None,
+            screen_timeout: None,
+            ..Settings::default()
+        };
+        assert_eq!(Settings::from_bytes(&never.to_bytes()), Ok(never));
+    }
+
+    #[test]
+    fn unsupported_versions() {
+        let mut bytes = settings().to_bytes();
+
+        bytes[0] = 0;
+        assert_eq!(
+            Settings::from_bytes(&bytes),
+            Err(DecodeError::UnsupportedVersion(0))
+        );
+
+        bytes[0] = SCHEMA_VERSION + 1;
+        assert_eq!(
+            Settings::from_bytes(&bytes),
+            Err(DecodeError::UnsupportedVersion(SCHEMA_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn truncated_blob_is_invalid() {
+        let bytes = settings().to_bytes();
+        for len in [0, 1, 5, bytes.len() - 1] {
+            assert_eq!(
+                Settings::from_bytes(&bytes[..len]),
+                Err(DecodeError::Invalid),
+                "{} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn migrate_v1_blob() {
+        #[rustfmt::skip]
+        let v1 = [
+            1, 42, 2,
+            1,
+            0x00, 0x00, 0x80, 0x3f, 0x00, 0x00, 0x00, 0x3f, 0x00, 0x00, 0x40, 0x40,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x3f, 0x00, 0x00, 0x00, 0xc0,
+            90, 0, 0, 0,
+            1,
+            5, b'f', b'r', b'-', b'F', b'R',
+        ];
+        assert_eq!(Settings::from_bytes(&v1), Ok(settings()));
+
+        let mut store = SettingsStore::new(MemoryStorage::new());
+        store.storage.write(SETTINGS_KEY, &v1).unwrap();
+        let loaded = store.load().unwrap().unwrap();
+        store.save(&loaded).unwrap();
+        assert_eq!(store.storage.values[SETTINGS_KEY][0], SCHEMA_VERSION);
+        assert!(matches!(store.load(), Ok(Some(loaded)) if loaded == settings()));
+
+        // The locale must be complete
+        assert_eq!(
+            Settings::from_bytes(&v1[..v1.len() - 1]),
+            Err(DecodeError::Invalid)
+        );
+    }
+
+    #[test]
+    fn rotation_change_clears_calibration() {
+        let mut settings = settings();
+        settings.set_rotation(Rotation::Deg180);
+        assert_eq!(settings.touch_calibration, Some(calibration()));
+
+        settings.set_rotation(Rotation::Deg90);
+        assert_eq!(settings.rotation, Rotation::Deg90);
+        assert_eq!(settings.touch_calibration, None);
+    }
+
+    #[test]
+    fn store_load_save_reset() {
+        let mut store = SettingsStore::new(MemoryStorage::new());
+        assert!(matches!(store.load(), Ok(None)));
+
+        store.save(&settings()).unwrap();
+        assert!(matches!(store.load(), Ok(Some(loaded)) if loaded == settings()));
+
+        store.reset().unwrap();
+        assert!(matches!(store.load(), Ok(None)));
+
+        // A blob from a newer firmware is not loaded
+        let mut storage = store.into_inner();
+        storage.write(SETTINGS_KEY, &[SCHEMA_VERSION + 1]).unwrap();
+        let store = SettingsStore::new(storage);
+        assert!(matches!(
+            store.load(),
+            Err(SettingsError::Decode(DecodeError::UnsupportedVersion(_)))
+        ));
+    }
+
+    #[test]
+    fn memory_storage_buffer_too_small() {
+        let mut storage = MemoryStorage::new();
+        storage.write("key", &[1, 2, 3]).unwrap();
+
+        let mut buf = [0u8; 2];
+        assert_eq!(storage.read("key", &mut buf), Err(BufferTooSmall));
+        let mut buf = [0u8; 4];
+        assert_eq!(storage.read("key", &mut buf), Ok(Some(&[1, 2, 3][..])));
+        assert_eq!(storage.remove("key"), Ok(true));
+        assert_eq!(storage.remove("key"), Ok(false));
+    }
+}
@@ -0,0 +1,139 @@
+//! Headless Lvgl display and touch input for running the UI on a PC.
+//!
+//! Lvgl renders into an in-memory framebuffer and the touch input is set by the caller (or replayed from a
+//! touch recording). The simulated time only advances when the simulator is run so the rendered images do not
+//! depend on how fast the PC is.
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Duration;
+
+use embedded_graphics_core::prelude::Point;
+use lvgl::input_device::{
+    pointer::{Pointer, PointerInputData},
+    InputDriver,
+};
+use lvgl::{Display, DrawBuffer};
+
+use crate::display_backend;
+use crate::framebuffer::Framebuffer;
+use crate::gt911::{TouchPoint, TouchState};
+use crate::touch_record::TouchReplay;
+
+pub const HOR_RES: u32 = 800;
+pub const VER_RES: u32 = 480;
+const LINES: u32 = 40; // The number of lines (rows) that will be refreshed
+
+/// Simulated time between calls to the Lvgl task handler
+pub const TICK: Duration = Duration::from_millis(10);
+
+pub struct Simulator {
+    display: Display,
+    _touch_screen: Pointer,
+    framebuffer: Rc<RefCell<Framebuffer>>,
+    touch: Rc<Cell<TouchState>>,
+    now: Duration,
+}
+
+impl Simulator {
+    /// Initializes Lvgl and registers an 800x480 display and a touch input device
+    pub fn new() -> Self {
+        lvgl::init();
+
+        let framebuffer = Rc::new(RefCell::new(Framebuffer::new(HOR_RES, VER_RES)));
+        let draw_buffer = DrawBuffer::<{ (HOR_RES * LINES) as usize }>::default();
+        let fb = framebuffer.clone();
+        let display = Display::register(draw_buffer, HOR_RES, VER_RES, move |refresh| {
+            // Drawing into the framebuffer can not fail
+            let _ = display_backend::flush(&mut *fb.borrow_mut(), refresh);
+        })
+        .unwrap();
+
+        let touch = Rc::new(Cell::new(TouchState::RELEASED(TouchPoint::default())));
+        let read_touch = touch.clone();
+        let read_touch_cb = move || match read_touch.get() {
+            TouchState::PRESSED(tp) => PointerInputData::Touch(Point {
+                x: tp.x as i32,
+                y: tp.y as i32,
+            })
+            .pressed()
+            .once(),
+            TouchState::RELEASED(tp) => PointerInputData::Touch(Point {
+                x: tp.x as i32,
+                y: tp.y as i32,
+            })
+            .released()
+            .once(),
+        };
+        let touch_screen = Pointer::register(read_touch_cb, &display).unwrap();
+
+        Self {
+            display,
+            _touch_screen: touch_screen,
+            framebuffer,
+            touch,
+            now: Duration::ZERO,
+        }
+    }
+
+    pub fn display(&self) -> &Display {
+        &self.display
+    }
+
+    /// The framebuffer Lvgl renders into
+    pub fn framebuffer(&self) -> std::cell::Ref<'_, Framebuffer> {
+        self.framebuffer.borrow()
+    }
+
+    /// The simulated time since the simulator was created
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// Set the touch state read by Lvgl
+    pub fn set_touch(&mut self, touch: TouchState) {
+        self.touch.set(touch);
+    }
+
+    /// Runs Lvgl for `duration` of simulated time
+    pub fn run_for(&mut self, duration: Duration) {
+        let end = self.now + duration;
+        while self.now < end {
+            self.step();
+        }
+    }
+
+    /// Presses and releases the touchscreen at `x`, `y`
+    pub fn tap(&mut self, x: u16, y: u16) {
+        let tp = TouchPoint {
+            x,
+            y,
+            ..TouchPoint::default()
+        };
+        self.set_touch(TouchState::PRESSED(tp));
+        self.run_for(Duration::from_millis(100));
+        self.set_touch(TouchState::RELEASED(tp));
+        self.run_for(Duration::from_millis(100));
+    }
+
+    /// Replays a touch recording, the recording timestamps are relative to the current simulated time
+    pub fn replay(&mut self, replay: &mut TouchReplay) {
+        let start = self.now;
+        while !replay.is_finished() {
+            self.set_touch(replay.state_at(self.now - start));
+            self.step();
+        }
+        self.set_touch(replay.state_at(self.now - start));
+    }
+
+    fn step(&mut self) {
+        lvgl::task_handler();
+        lvgl::tick_inc(TICK);
+        self.now += TICK;
+    }
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
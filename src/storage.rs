@@ -0,0 +1,147 @@
+//! The app_storage FAT partition.
+//!
+//! The partition (see partition-table/partitions.csv) is mounted through the VFS with wear levelling so the files
+//! on it are opened with std::fs under the mount point. Registering the partition as an Lvgl drive (see
+//! lvgl_fs.rs) lets Lvgl load images and fonts from eg "S:/images/logo.bin" at runtime instead of building them
+//! into the app image.
+use std::ffi::CString;
+
+use log::*;
+
+use esp_idf_svc::sys::{
+    esp, esp_vfs_fat_mount_config_t, esp_vfs_fat_spiflash_mount_rw_wl,
+    esp_vfs_fat_spiflash_unmount_rw_wl, wl_handle_t, EspError, ESP_ERR_INVALID_ARG,
+};
+
+use crate::lvgl_fs::{self, FsDriverError};
+
+/// Maximum length of the mount point, ESP_VFS_PATH_MAX in ESP-IDF
+const MAX_MOUNT_POINT_LEN: usize = 15;
+
+/// Storage configuration
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct StorageConfig {
+    /// Label of the FAT partition in the partition table
+    pub partition_label: &'static str,
+    /// Path the partition is mounted on, must start with '/'
+    pub mount_point: &'static str,
+    /// Maximum number of files open at the same time
+    pub max_files: i32,
+    /// Format the partition if it can not be mounted, a new partition is empty until it is formatted
+    pub format_if_mount_failed: bool,
+}
+
+impl StorageConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn partition_label(mut self, label: &'static str) -> Self {
+        self.partition_label = label;
+        self
+    }
+
+    #[must_use]
+    pub fn mount_point(mut self, path: &'static str) -> Self {
+        self.mount_point = path;
+        self
+    }
+
+    #[must_use]
+    pub fn max_files(mut self, max_files: i32) -> Self {
+        self.max_files = max_files;
+        self
+    }
+
+    #[must_use]
+    pub fn format_if_mount_failed(mut self, format: bool) -> Self {
+        self.format_if_mount_failed = format;
+        self
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            partition_label: "app_storage",
+            mount_point: "/storage",
+            max_files: 5,
+            format_if_mount_failed: true,
+        }
+    }
+}
+
+/// The mounted FAT partition, the partition is unmounted when dropped.
+///
+/// A registered Lvgl drive (see `register_lvgl_drive`) is not unregistered when the partition is unmounted, keep
+/// the Storage for as long as Lvgl runs.
+pub struct Storage {
+    mount_point: CString,
+    wl_handle: wl_handle_t,
+}
+
+impl Storage {
+    pub fn mount(config: &StorageConfig) -> Result<Self, EspError> {
+        if !config.mount_point.starts_with('/') || config.mount_point.len() > MAX_MOUNT_POINT_LEN {
+            warn!(
+                "Mount point {} must start with '/' and be at most {} characters",
+                config.mount_point, MAX_MOUNT_POINT_LEN
+            );
+            return Err(EspError::from_infallible::<ESP_ERR_INVALID_ARG>());
+        }
+
+        let mount_point = CString::new(config.mount_point)
+            .map_err(|_| EspError::from_infallible::<ESP_ERR_INVALID_ARG>())?;
+        let partition_label = CString::new(config.partition_label)
+            .map_err(|_| EspError::from_infallible::<ESP_ERR_INVALID_ARG>())?;
+
+        let mount_config = esp_vfs_fat_mount_config_t {
+            format_if_mount_failed: config.format_if_mount_failed,
+            max_files: config.max_files,
+            // The sector size (4096 bytes) of the wear levelling layer
+            allocation_unit_size: 0,
+            ..Default::default()
+        };
+
+        let mut wl_handle: wl_handle_t = -1;
+        esp!(unsafe {
+            esp_vfs_fat_spiflash_mount_rw_wl(
+                mount_point.as_ptr(),
+                partition_label.as_ptr(),
+                &mount_config,
+                &mut wl_handle,
+            )
+        })?;
+
+        Ok(Self {
+            mount_point,
+            wl_handle,
+        })
+    }
+
+    /// The path the partition is mounted on
+    pub fn mount_point(&self) -> &str {
+        // The mount point was created from a &str
+        self.mount_point.to_str().unwrap()
+    }
+
+    /// Registers the partition as the Lvgl drive `letter`, must be called after `lvgl::init`.
+    ///
+    /// Lvgl has no way to unregister a drive, the Storage must outlive Lvgl or Lvgl fails to open files on the
+    /// drive once the partition is unmounted.
+    pub fn register_lvgl_drive(&self, letter: char) -> Result<(), FsDriverError> {
+        lvgl_fs::register(letter, self.mount_point())
+    }
+}
+
+impl Drop for Storage {
+    fn drop(&mut self) {
+        let result = esp!(unsafe {
+            esp_vfs_fat_spiflash_unmount_rw_wl(self.mount_point.as_ptr(), self.wl_handle)
+        });
+        if let Err(e) = result {
+            warn!("Failed to unmount {}: {}", self.mount_point(), e);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! Data ready signalling from the GT911 INT pin.
+//!
+//! The GT911 pulses the INT pin every time it has new touch data (while the screen is touched and once more
+//! when the touch is released). The GPIO interrupt sets a flag that is checked by the Lvgl touch callback so
+//! the GT911 is only read over I2C when there is new data.
+//!
+//! Only one `TouchInterrupt` can exist since the data ready flag is shared with the interrupt handler.
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use log::*;
+
+use esp_idf_svc::hal::gpio::{AnyIOPin, Input, InterruptType, PinDriver};
+use esp_idf_svc::sys::EspError;
+
+// Set by the interrupt handler, start as true so the GT911 is read once at startup
+static DATA_READY: AtomicBool = AtomicBool::new(true);
+
+pub struct TouchInterrupt {
+    pin: PinDriver<'static, AnyIOPin, Input>,
+}
+
+impl TouchInterrupt {
+    /// Subscribe to the INT pin interrupt, the GT911 must already have been reset and the INT pin released
+    /// (configured as an input).
+    pub fn new(mut pin: PinDriver<'static, AnyIOPin, Input>) -> Result<Self, EspError> {
+        // The GT911 can be configured to pulse INT high or low so trigger on any edge
+        pin.set_interrupt_type(InterruptType::AnyEdge)?;
+
+        // SAFETY: the callback runs in the GPIO interrupt, it only stores to the DATA_READY atomic which is ISR
+        // safe, it does not allocate, block or log and it borrows nothing so it is 'static
+        unsafe {
+            pin.subscribe(|| {
+                DATA_READY.store(true, Ordering::Release);
+            })?;
+        }
+
+        pin.enable_interrupt()?;
+
+        Ok(Self { pin })
+    }
+
+    /// Returns true if the GT911 signalled new touch data since the last call.
+    pub fn take_data_ready(&mut self) -> bool {
+        let data_ready = DATA_READY.swap(false, Ordering::Acquire);
+
+        // The interrupt is disabled after it fires, enable it again to be notified of the next touch data
+        if data_ready {
+            if let Err(e) = self.pin.enable_interrupt() {
+                warn!("Failed to enable touch interrupt: {}", e);
+            }
+        }
+
+        data_ready
+    }
+}
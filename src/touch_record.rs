@@ -0,0 +1,355 @@
+//! Recording and replay of the touch states read by Lvgl.
+//!
+//! The touch states are recorded as CSV, one line per change of state:
+//!
+//! ```text
+//! # timestamp_ms,state,x,y
+//! 1520,P,95,241
+//! 1610,R,95,241
+//! ```
+//!
+//! The state is `P` for PRESSED and `R` for RELEASED. Empty lines and lines starting with `#` are ignored.
+//! A recording can be replayed with `TouchReplay` in place of the GT911 to reproduce a bug report or to run the
+//! same touch sequence against the UI every time.
+use core::fmt;
+use core::time::Duration;
+use std::io::{self, BufRead, Write};
+
+use crate::gt911::{TouchPoint, TouchState};
+
+/// A touch state and the time it was read
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TouchEvent {
+    pub timestamp: Duration,
+    pub state: TouchState,
+}
+
+impl TouchEvent {
+    /// Formats the event as a CSV line (without the line ending)
+    pub fn to_csv(&self) -> String {
+        let (state, tp) = match self.state {
+            TouchState::PRESSED(tp) => ('P', tp),
+            TouchState::RELEASED(tp) => ('R', tp),
+        };
+
+        format!("{},{},{},{}", self.timestamp.as_millis(), state, tp.x, tp.y)
+    }
+
+    /// Parses a CSV line written by `to_csv`
+    pub fn from_csv(line: &str) -> Option<Self> {
+        let mut fields = line.split(',').map(str::trim);
+
+        let timestamp = Duration::from_millis(fields.next()?.parse().ok()?);
+        let state = fields.next()?;
+        let x = fields.next()?.parse().ok()?;
+        let y = fields.next()?.parse().ok()?;
+        if fields.next().is_some() {
+            return None;
+        }
+
+        let tp = TouchPoint {
+            x,
+            y,
+            ..TouchPoint::default()
+        };
+        let state = match state {
+            "P" => TouchState::PRESSED(tp),
+            "R" => TouchState::RELEASED(tp),
+            _ => return None,
+        };
+
+        Some(Self { timestamp, state })
+    }
+}
+
+/// Error returned when a recording can not be read
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    /// The line (1 based) is not a valid touch event
+    InvalidLine(usize),
+    /// The timestamp on the line (1 based) is earlier than the previous timestamp
+    OutOfOrder(usize),
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(error: io::Error) -> Self {
+        ReplayError::Io(error)
+    }
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "failed to read touch recording: {}", e),
+            ReplayError::InvalidLine(line) => write!(f, "invalid touch event on line {}", line),
+            ReplayError::OutOfOrder(line) => {
+                write!(f, "touch event on line {} is out of order", line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+/// Records the touch states, only changes of state are written to keep the recording small
+pub struct TouchRecorder<W: Write> {
+    writer: W,
+    last: Option<TouchState>,
+}
+
+impl<W: Write> TouchRecorder<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writeln!(writer, "# timestamp_ms,state,x,y")?;
+
+        Ok(Self { writer, last: None })
+    }
+
+    /// Records the touch state if it is different from the last recorded state
+    pub fn record(&mut self, timestamp: Duration, state: TouchState) -> io::Result<()> {
+        if self.last.is_some_and(|last| same_state(&last, &state)) {
+            return Ok(());
+        }
+        self.last = Some(state);
+
+        writeln!(self.writer, "{}", TouchEvent { timestamp, state }.to_csv())?;
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+// Compare the state and coordinates, the track id and size are not recorded
+fn same_state(a: &TouchState, b: &TouchState) -> bool {
+    match (a, b) {
+        (TouchState::PRESSED(a), TouchState::PRESSED(b))
+        | (TouchState::RELEASED(a), TouchState::RELEASED(b)) => a.x == b.x && a.y == b.y,
+        _ => false,
+    }
+}
+
+/// Replays a recording as touch input
+pub struct TouchReplay {
+    events: Vec<TouchEvent>,
+    next: usize,
+    current: TouchState,
+}
+
+impl TouchReplay {
+    pub fn new(events: Vec<TouchEvent>) -> Self {
+        Self {
+            events,
+            next: 0,
+            current: TouchState::RELEASED(TouchPoint::default()),
+        }
+    }
+
+    /// Reads a recording written by `TouchRecorder`
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Self, ReplayError> {
+        let mut events: Vec<TouchEvent> = Vec::new();
+
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let event = TouchEvent::from_csv(line).ok_or(ReplayError::InvalidLine(index + 1))?;
+            if events
+                .last()
+                .is_some_and(|last| last.timestamp > event.timestamp)
+            {
+                return Err(ReplayError::OutOfOrder(index + 1));
+            }
+            events.push(event);
+        }
+
+        Ok(Self::new(events))
+    }
+
+    /// Returns the recorded touch state at `timestamp`. Timestamps must not go backwards. Before the first event
+    /// the touch is RELEASED.
+    ///
+    /// At most one event is replayed per call so every recorded state is returned once, a tap pressed and released
+    /// between two calls is returned as PRESSED and then RELEASED on the next call instead of being lost. The
+    /// replay falls behind the recording by one call for each event that was due at the same time.
+    pub fn state_at(&mut self, timestamp: Duration) -> TouchState {
+        if let Some(event) = self.events.get(self.next) {
+            if event.timestamp <= timestamp {
+                self.current = event.state;
+                self.next += 1;
+            }
+        }
+
+        self.current
+    }
+
+    /// Returns true when all the recorded events have been replayed
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.events.len()
+    }
+
+    /// Start the replay again from the first event
+    pub fn rewind(&mut self) {
+        self.next = 0;
+        self.current = TouchState::RELEASED(TouchPoint::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: u16, y: u16) -> TouchPoint {
+        TouchPoint {
+            x,
+            y,
+            ..TouchPoint::default()
+        }
+    }
+
+    fn event(ms: u64, state: TouchState) -> TouchEvent {
+        TouchEvent {
+            timestamp: Duration::from_millis(ms),
+            state,
+        }
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        for event in [
+            event(0, TouchState::RELEASED(point(0, 0))),
+            event(1520, TouchState::PRESSED(point(95, 241))),
+            event(1610, TouchState::RELEASED(point(799, 479))),
+        ] {
+            assert_eq!(TouchEvent::from_csv(&event.to_csv()), Some(event));
+        }
+        assert_eq!(
+            event(1520, TouchState::PRESSED(point(95, 241))).to_csv(),
+            "1520,P,95,241"
+        );
+        assert_eq!(
+            TouchEvent::from_csv(" 1520 , P , 95 , 241 "),
+            Some(event(1520, TouchState::PRESSED(point(95, 241))))
+        );
+    }
+
+    #[test]
+    fn invalid_csv() {
+        for line in [
+            "",
+            "1520,P,95",
+            "1520,P,95,241,1",
+            "1520,X,95,241",
+            "-1,P,95,241",
+            "1520,P,95,70000",
+            "t,P,95,241",
+        ] {
+            assert_eq!(TouchEvent::from_csv(line), None, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn replay_error_lines() {
+        let recording = "# timestamp_ms,state,x,y\n\n100,P,1,1\n100,R,1,1\nnot an event\n";
+        assert!(matches!(
+            TouchReplay::from_reader(recording.as_bytes()),
+            Err(ReplayError::InvalidLine(5))
+        ));
+
+        let recording = "100,P,1,1\n# comment\n200,R,1,1\n150,P,1,1\n";
+        let error = TouchReplay::from_reader(recording.as_bytes())
+            .err()
+            .unwrap();
+        assert!(matches!(error, ReplayError::OutOfOrder(4)));
+        assert_eq!(error.to_string(), "touch event on line 4 is out of order");
+    }
+
+    #[test]
+    fn recorder_writes_changes_only() {
+        let mut recorder = TouchRecorder::new(Vec::new()).unwrap();
+        let ms = Duration::from_millis;
+        recorder
+            .record(ms(0), TouchState::RELEASED(point(0, 0)))
+            .unwrap();
+        recorder
+            .record(ms(30), TouchState::RELEASED(point(0, 0)))
+            .unwrap();
+        recorder
+            .record(ms(60), TouchState::PRESSED(point(10, 20)))
+            .unwrap();
+        // The track id and size are not recorded
+        let same = TouchPoint {
+            track_id: 3,
+            size: 40,
+            ..point(10, 20)
+        };
+        recorder.record(ms(90), TouchState::PRESSED(same)).unwrap();
+        recorder
+            .record(ms(120), TouchState::PRESSED(point(11, 20)))
+            .unwrap();
+        recorder
+            .record(ms(150), TouchState::RELEASED(point(11, 20)))
+            .unwrap();
+
+        let recording = String::from_utf8(recorder.into_inner()).unwrap();
+        assert_eq!(
+            recording,
+            "# timestamp_ms,state,x,y\n0,R,0,0\n60,P,10,20\n120,P,11,20\n150,R,11,20\n"
+        );
+
+        let replay = TouchReplay::from_reader(recording.as_bytes()).unwrap();
+        assert_eq!(replay.events.len(), 4);
+    }
+
+    #[test]
+    fn state_at_and_rewind() {
+        let ms = Duration::from_millis;
+        let mut replay = TouchReplay::new(vec![
+            event(100, TouchState::PRESSED(point(10, 20))),
+            event(200, TouchState::RELEASED(point(10, 20))),
+        ]);
+
+        assert_eq!(replay.state_at(ms(0)), TouchState::RELEASED(point(0, 0)));
+        assert_eq!(replay.state_at(ms(100)), TouchState::PRESSED(point(10, 20)));
+        assert_eq!(replay.state_at(ms(150)), TouchState::PRESSED(point(10, 20)));
+        assert!(!replay.is_finished());
+        assert_eq!(
+            replay.state_at(ms(250)),
+            TouchState::RELEASED(point(10, 20))
+        );
+        assert!(replay.is_finished());
+        assert_eq!(
+            replay.state_at(ms(300)),
+            TouchState::RELEASED(point(10, 20))
+        );
+
+        replay.rewind();
+        assert!(!replay.is_finished());
+        assert_eq!(replay.state_at(ms(50)), TouchState::RELEASED(point(0, 0)));
+        assert_eq!(replay.state_at(ms(100)), TouchState::PRESSED(point(10, 20)));
+    }
+
+    #[test]
+    fn short_tap_between_polls_is_replayed() {
+        let ms = Duration::from_millis;
+        let mut replay = TouchReplay::new(vec![
+            event(105, TouchState::PRESSED(point(10, 20))),
+            event(115, TouchState::RELEASED(point(10, 20))),
+            event(125, TouchState::PRESSED(point(30, 40))),
+        ]);
+
+        // Polled every 30 ms, all three events fall between the polls at 90 and 120 ms
+        assert_eq!(replay.state_at(ms(90)), TouchState::RELEASED(point(0, 0)));
+        assert_eq!(replay.state_at(ms(120)), TouchState::PRESSED(point(10, 20)));
+        assert_eq!(
+            replay.state_at(ms(150)),
+            TouchState::RELEASED(point(10, 20))
+        );
+        assert_eq!(replay.state_at(ms(180)), TouchState::PRESSED(point(30, 40)));
+        assert!(replay.is_finished());
+    }
+}
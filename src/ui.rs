@@ -0,0 +1,61 @@
+//! The clickme user interface.
+//!
+//! The UI only depends on Lvgl so the same code runs on the ESP32S3 and in the desktop simulator.
+use cstr_core::CString;
+
+use lvgl::style::Style;
+use lvgl::widgets::{Btn, Label};
+use lvgl::{Align, Color, Display, Part, Widget};
+
+/// Holds the widgets and styles of the UI, Lvgl keeps pointers to the styles so this must be kept alive as long
+/// as the UI is shown.
+pub struct ClickMeUi {
+    _screen_style: Style,
+    _button: Btn,
+}
+
+/// Creates the UI on the active screen of `display`
+///
+/// A large button that shows "Click me!", when the button is clicked it shows "Clicked!", clicking the button
+/// again shows "Click me!".
+pub fn create_ui(display: &Display) -> ClickMeUi {
+    // Create screen and widgets
+    let mut screen = display.get_scr_act().unwrap();
+    let mut screen_style = Style::default();
+    screen_style.set_bg_color(Color::from_rgb((0, 0, 139)));
+    screen_style.set_radius(0);
+    screen.add_style(Part::Main, &mut screen_style);
+
+    // Create the button
+    let mut button = Btn::create(&mut screen).unwrap();
+    button.set_align(Align::LeftMid, 30, 0);
+    button.set_size(180, 80);
+
+    // Create button label, align in center of button
+    let mut btn_lbl = Label::create(&mut button).unwrap();
+    btn_lbl.set_align(Align::Center, 0, 0);
+    btn_lbl.set_text(CString::new("Click me!").unwrap().as_c_str());
+
+    // The label and button state are moved into the event closure since it outlives this function
+    let mut btn_state = false;
+    button
+        .on_event(move |_btn, event| {
+            if let lvgl::Event::Clicked = event {
+                println!("Clicked Event");
+                if btn_state {
+                    let nt = CString::new("Click me!").unwrap();
+                    btn_lbl.set_text(nt.as_c_str());
+                } else {
+                    let nt = CString::new("Clicked!").unwrap();
+                    btn_lbl.set_text(nt.as_c_str());
+                }
+                btn_state = !btn_state;
+            }
+        })
+        .unwrap();
+
+    ClickMeUi {
+        _screen_style: screen_style,
+        _button: button,
+    }
+}
@@ -0,0 +1,145 @@
+//! Tear free rendering with two frame buffers synchronized to the panel VSYNC.
+//!
+//! Lvgl renders the whole screen (full refresh) straight into the back frame buffer. The flush callback hands
+//! the frame buffer to the panel, which switches to it at the end of the current frame, and Lvgl is only told
+//! the flush is done (`lv_disp_flush_ready`) on the following VSYNC. Lvgl therefore never draws into the frame
+//! buffer the panel is reading from and there is no tearing during animations.
+//!
+//! Only one panel can use VSYNC flushing since the panel and the flushing display driver are shared with the
+//! interrupt handler.
+use core::ffi::c_void;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use log::*;
+
+use esp_idf_svc::sys::{
+    esp, esp_lcd_panel_draw_bitmap, esp_lcd_panel_handle_t, esp_lcd_panel_t,
+    esp_lcd_rgb_panel_event_callbacks_t, esp_lcd_rgb_panel_event_data_t, EspError,
+    ESP_ERR_INVALID_STATE,
+};
+
+use crate::display_backend::DisplayBackend;
+use crate::draw_buffer;
+use crate::lcd_panel::LcdPanel;
+use crate::panel_config::ColorMode;
+use crate::rotation::Rotation;
+
+// The panel the frame buffers are flushed to
+static PANEL: AtomicPtr<esp_lcd_panel_t> = AtomicPtr::new(null_mut());
+
+// The Lvgl display driver waiting for the next VSYNC, set by the flush callback and cleared by the VSYNC interrupt
+static FLUSHING: AtomicPtr<lvgl_sys::lv_disp_drv_t> = AtomicPtr::new(null_mut());
+
+/// Registers an Lvgl display that renders into the first two frame buffers of `lcd_panel` and flushes on VSYNC.
+/// The display becomes the default display when it is the first display registered, get it with
+/// `lvgl::Display::default()`.
+///
+/// The panel must have been created with two frame buffers (num_fbs 2 or the double_fb flag) and must outlive
+/// the Lvgl display. Lvgl renders straight into the frame buffers so the display can not be rotated. Must be
+/// called after `lvgl::init` and before the first `lvgl::task_handler`.
+///
+/// The VSYNC callback runs from flash so this fails when the LCD interrupt is IRAM safe
+/// (CONFIG_LCD_RGB_ISR_IRAM_SAFE), the interrupt then also runs while the flash cache is disabled.
+pub fn register(lcd_panel: &mut LcdPanel) -> Result<(), EspError> {
+    if cfg!(esp_idf_lcd_rgb_isr_iram_safe) {
+        warn!("VSYNC flushing can not be used with CONFIG_LCD_RGB_ISR_IRAM_SAFE");
+        return Err(EspError::from_infallible::<ESP_ERR_INVALID_STATE>());
+    }
+
+    if lcd_panel.rotation() != Rotation::Deg0 {
+        warn!("VSYNC flushing does not support display rotation");
+        return Err(EspError::from_infallible::<ESP_ERR_INVALID_STATE>());
+    }
+
+    if lcd_panel.refresh_on_demand() {
+        warn!("VSYNC flushing needs a panel that is refreshed continuously");
+        return Err(EspError::from_infallible::<ESP_ERR_INVALID_STATE>());
+    }
+
+    // Lvgl renders RGB565 so the frame buffers must be RGB565
+    if lcd_panel.color_mode() != ColorMode::Rgb565 {
+        warn!("VSYNC flushing needs the RGB565 color mode");
+        return Err(EspError::from_infallible::<ESP_ERR_INVALID_STATE>());
+    }
+
+    let (fb0, fb1) = match lcd_panel.frame_buffer_ptrs() {
+        [fb0, fb1, ..] => (*fb0, *fb1),
+        _ => {
+            warn!("VSYNC flushing needs two frame buffers");
+            return Err(EspError::from_infallible::<ESP_ERR_INVALID_STATE>());
+        }
+    };
+
+    PANEL.store(lcd_panel.panel, Ordering::Release);
+
+    let callbacks = esp_lcd_rgb_panel_event_callbacks_t {
+        on_vsync: Some(on_vsync),
+        ..Default::default()
+    };
+    unsafe {
+        lcd_panel.register_event_callbacks(&callbacks, null_mut())?;
+    }
+
+    // Redraw the whole screen every time so both frame buffers hold the complete screen
+    let (hor_res, ver_res) = lcd_panel.resolution();
+    // SAFETY: the frame buffers are owned by the panel, which must outlive the Lvgl display
+    unsafe {
+        draw_buffer::register_driver(
+            (hor_res, ver_res),
+            [fb0, fb1],
+            hor_res * ver_res,
+            flush_cb,
+            null_mut(),
+            true,
+        )
+    }
+}
+
+// Hand the frame buffer Lvgl rendered into over to the panel, Lvgl is told the flush is done on the next VSYNC
+unsafe extern "C" fn flush_cb(
+    drv: *mut lvgl_sys::lv_disp_drv_t,
+    area: *const lvgl_sys::lv_area_t,
+    color_p: *mut lvgl_sys::lv_color_t,
+) {
+    let panel = PANEL.load(Ordering::Acquire);
+    let area = &*area;
+
+    // color_p is a frame buffer so nothing is copied, the driver writes back the cache and switches to the
+    // frame buffer at the end of the current frame
+    let result = esp!(esp_lcd_panel_draw_bitmap(
+        panel,
+        area.x1.into(),
+        area.y1.into(),
+        (area.x2 + 1).into(),
+        (area.y2 + 1).into(),
+        color_p as *const c_void,
+    ));
+
+    match result {
+        Ok(()) => FLUSHING.store(drv, Ordering::Release),
+        Err(e) => {
+            warn!("Failed to flush frame buffer: {}", e);
+            lvgl_sys::lv_disp_flush_ready(drv);
+        }
+    }
+}
+
+// Called from the LCD interrupt at every VSYNC, the panel has switched to the flushed frame buffer.
+//
+// This runs in interrupt context: it must not block, allocate or log. lv_disp_flush_ready only clears the flushing
+// flags of the draw buffer, Lvgl allows it to be called from an interrupt. The callback and Lvgl are in flash, see
+// the IRAM safe check in `register`.
+unsafe extern "C" fn on_vsync(
+    _panel: esp_lcd_panel_handle_t,
+    _edata: *const esp_lcd_rgb_panel_event_data_t,
+    _user_ctx: *mut c_void,
+) -> bool {
+    let drv = FLUSHING.swap(null_mut(), Ordering::AcqRel);
+    if !drv.is_null() {
+        lvgl_sys::lv_disp_flush_ready(drv);
+    }
+
+    // No higher priority task was woken
+    false
+}
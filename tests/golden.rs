@@ -0,0 +1,98 @@
+//! Golden image tests for the clickme UI.
+//!
+//! The UI is rendered with the simulator and compared against the reference images in the goldens folder.
+//! Lvgl is not thread safe so the tests run one after another in a single simulator (harness = false).
+//!
+//! cargo test --test golden --features simulator --target x86_64-unknown-linux-gnu
+//!
+//! Set UPDATE_GOLDENS=1 to write the rendered images as the new reference images.
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use rust_esp32s3_lvgl_clickme::golden::{compare, Image, Tolerance};
+use rust_esp32s3_lvgl_clickme::simulator::Simulator;
+use rust_esp32s3_lvgl_clickme::ui::create_ui;
+
+// Center of the "Click me!" button
+const BUTTON_X: u16 = 120;
+const BUTTON_Y: u16 = 240;
+
+// Time for Lvgl to finish any animations before taking a screenshot
+const SETTLE_TIME: Duration = Duration::from_millis(1000);
+
+fn main() {
+    let update = std::env::var("UPDATE_GOLDENS").is_ok_and(|v| v == "1");
+
+    let mut simulator = Simulator::new();
+    let _ui = create_ui(simulator.display());
+
+    let mut failures = 0;
+
+    simulator.run_for(SETTLE_TIME);
+    failures += check_golden(&simulator, "click_me", update);
+
+    simulator.tap(BUTTON_X, BUTTON_Y);
+    simulator.run_for(SETTLE_TIME);
+    failures += check_golden(&simulator, "clicked", update);
+
+    if failures > 0 {
+        eprintln!("{} golden image(s) did not match", failures);
+        std::process::exit(1);
+    }
+}
+
+// Compare the current screen against goldens/<name>.ppm, returns the number of failures (0 or 1)
+fn check_golden(simulator: &Simulator, name: &str, update: bool) -> usize {
+    let actual = Image::from_framebuffer(&simulator.framebuffer());
+    let golden_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("goldens")
+        .join(format!("{}.ppm", name));
+
+    if update {
+        write_image(&actual, &golden_path);
+        println!("{}: updated {}", name, golden_path.display());
+        return 0;
+    }
+
+    let expected = match File::open(&golden_path).and_then(|f| Image::read_ppm(BufReader::new(f))) {
+        Ok(expected) => expected,
+        Err(e) => {
+            eprintln!(
+                "{}: failed to read {} ({}), run with UPDATE_GOLDENS=1 to create it",
+                name,
+                golden_path.display(),
+                e
+            );
+            return 1;
+        }
+    };
+
+    match compare(&actual, &expected, Tolerance::default()) {
+        Ok(()) => {
+            println!("{}: ok", name);
+            0
+        }
+        Err(mismatch) => {
+            let actual_path =
+                PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.ppm", name));
+            write_image(&actual, &actual_path);
+            eprintln!(
+                "{}: {}, rendered image written to {}",
+                name,
+                mismatch,
+                actual_path.display()
+            );
+            1
+        }
+    }
+}
+
+fn write_image(image: &Image, path: &PathBuf) {
+    let file =
+        File::create(path).unwrap_or_else(|e| panic!("failed to create {}: {}", path.display(), e));
+    image
+        .write_ppm(BufWriter::new(file))
+        .unwrap_or_else(|e| panic!("failed to write {}: {}", path.display(), e));
+}